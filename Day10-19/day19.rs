@@ -1,222 +1,38 @@
+extern crate intcode;
+
+use intcode::search::Search;
+use intcode::{StopReason, VM};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Read};
-use std::iter;
-use std::path::Path;
 use std::{thread, time};
 
 const INTERACTIVE: bool = false;
 
-#[derive(Debug)]
-struct VM {
-    state: Vec<i64>,
-    pc: usize,
-    input: Vec<i64>,
-    output: Option<i64>,
-    halted: bool,
-    relative_base: i64,
-    waiting_for_input: bool,
-}
-
-impl VM {
-    fn next_value(&mut self) -> i64 {
-        let v = self.state[self.pc];
-        self.pc += 1;
-        v
-    }
-
-    fn get_params(&mut self, code: i64, num_params: i64) -> Vec<i64> {
-        let mut params = Vec::with_capacity(num_params as usize);
-        for i in 0..num_params {
-            let p = self.next_value();
-            let mode = (code % 10_i64.pow(3 + i as u32)) / (10_i64.pow(2 + i as u32));
-            match mode {
-                // Position
-                0 => params.push(self.state[p as usize]),
-                // Immediate
-                1 => params.push(p),
-                // Relative
-                2 => params.push(self.state[(p + self.relative_base) as usize]),
-                _ => panic!("Invalid mode: {}", mode),
-            }
-            // println!("\t{} => {}", p, params.last().unwrap());
-        }
-        // println!("{} {:?}", code, params);
-        params
-    }
-
-    fn get_write_location(&mut self, code: i64, num_params: i64) -> usize {
-        let mode = (code % 10_i64.pow(2 + num_params as u32)) / (10_i64.pow(1 + num_params as u32));
-        let p = self.next_value() as usize;
-        let pos = match mode {
-            // Position
-            0 => p,
-            // Relative
-            2 => ((p as i64) + self.relative_base) as usize,
-            _ => panic!("Invalid mode: {}", mode),
-        };
-        pos
-    }
-
-    fn run(&mut self) {
-        while !self.halted {
-            self.run_one();
-        }
-    }
-
-    fn run_one(&mut self) {
-        if self.halted || self.waiting_for_input {
-            return;
-        }
-
-        // println!("---");
-        // println!("instruction: {:?}", &self.state[self.pc..=self.pc+3]);
-        // println!("relative base: {}", self.relative_base);
-        let op_code = self.next_value();
-        match op_code % 100 {
-            // Add
-            1 => {
-                let params = self.get_params(op_code, 2);
-                let pos = self.get_write_location(op_code, 3);
-                self.set_state(pos, params[0] + params[1]);
-            }
-            // Mult
-            2 => {
-                let params = self.get_params(op_code, 2);
-                let pos = self.get_write_location(op_code, 3);
-                self.set_state(pos, params[0] * params[1]);
-            }
-            // Input
-            3 => {
-                let idx = self.get_write_location(op_code, 1);
-                if !self.input.is_empty() {
-                    let input = self.input.remove(0);
-                    self.set_state(idx, input);
-                } else {
-                    self.pc -= 2;
-                    self.waiting_for_input = true;
-                }
-            }
-            // Output
-            4 => {
-                let params = self.get_params(op_code, 1);
-                self.output = Some(params[0]);
-                // println!("Output: {:?}", self.output);
-            }
-            // Jump-Non-Zero
-            5 => {
-                let params = self.get_params(op_code, 2);
-                if params[0] != 0 {
-                    self.pc = params[1] as usize;
-                    // println!("jumped to {}", params[1]);
-                }
-            }
-            // Jump-Eq-Zero
-            6 => {
-                let params = self.get_params(op_code, 2);
-                if params[0] == 0 {
-                    self.pc = params[1] as usize;
-                    // println!("jumped to {}", params[1]);
-                }
-            }
-            // Less Than
-            7 => {
-                let params = self.get_params(op_code, 2);
-                let idx = self.get_write_location(op_code, 3);
-                self.set_state(idx, if params[0] < params[1] { 1 } else { 0 });
-            }
-            // Equal
-            8 => {
-                let params = self.get_params(op_code, 2);
-                let idx = self.get_write_location(op_code, 3);
-                self.set_state(idx, if params[0] == params[1] { 1 } else { 0 });
-            }
-            // Adjust relative base
-            9 => {
-                let params = self.get_params(op_code, 1);
-                self.relative_base += params[0];
-                // println!("new relative base: {}", self.relative_base);
-            }
-            // Halt
-            99 => self.halted = true,
-            _ => panic!("Unknown opcode: {}", op_code % 100),
-        }
-    }
-
-    fn run_until_output(&mut self) -> Option<i64> {
-        while !self.halted && !self.waiting_for_input {
-            self.run_one();
-            if let Some(output) = self.output {
-                self.output = None;
-                return Some(output);
-            }
-        }
-        None
-    }
-
-    fn new(initial_state: Vec<i64>) -> VM {
-        VM {
-            state: initial_state,
-            pc: 0,
-            output: None,
-            input: Vec::new(),
-            halted: false,
-            relative_base: 0,
-            waiting_for_input: false,
-        }
-    }
-
-    pub fn set_state(&mut self, pc: usize, val: i64) {
-        self.state[pc as usize] = val;
-        // println!("set location {} to value {}", pc, val);
-    }
-
-    pub fn push_input(&mut self, input: i64) {
-        self.input.push(input);
-        self.waiting_for_input = false;
-    }
-}
-
-fn read_file<P>(filename: P) -> io::Result<String>
-where
-    P: AsRef<Path>,
-{
-    let mut file = File::open(filename)?;
-    let mut out = String::new();
-    file.read_to_string(&mut out)?;
-    Ok(out)
-}
-
-fn is_affected(inp: &Vec<i64>, x: i64, y: i64) -> bool {
-    let mut vm = VM::new(inp.to_vec());
+fn is_affected(drone: &mut VM, x: i64, y: i64) -> bool {
+    let mut vm = drone.fork();
     vm.push_input(x);
     vm.push_input(y);
-    vm.run_until_output().unwrap_or(0) == 1
+    vm.resume().unwrap() == StopReason::Output(1)
 }
 
 pub fn main() {
-    let mut input: Vec<i64> = read_file("19.txt")
-        .expect("file doesn't exist")
-        .split(",")
-        .map(|x| x.trim().parse().unwrap())
-        .collect();
-    input.extend(iter::repeat(0).take(10000));
-
-
-    let mut part1 = 0;
-    for x in 0..50 {
-        for y in 0..50 {
-            if is_affected(&input, x, y) {
-                part1 += 1;
-            }
-        }
-    }
+    let input = intcode::read_program("19.txt").expect("file doesn't exist");
+    // Every probe starts from the drone program waiting for its coordinates
+    let mut drone = VM::new(input.clone());
+    assert_eq!(drone.resume().unwrap(), StopReason::NeedsInput);
+
+
+    let search = Search::new(&input, |&(x, y): &(i64, i64), vm| {
+        vm.push_input(x);
+        vm.push_input(y);
+    });
+    let grid = (0..50).flat_map(|x| (0..50).map(move |y| (x, y)));
+    let part1 = search.all(grid, |_, run| run.outputs == [1]).len();
     println!("Part 1: {}", part1);
 
     let (mut x, mut y) = (0, 0);
-    while !is_affected(&input, x+99, y) {
+    while !is_affected(&mut drone, x+99, y) {
         y += 1;
-        while !is_affected(&input, x, y+99) {
+        while !is_affected(&mut drone, x, y+99) {
             x += 1;
         }
     }
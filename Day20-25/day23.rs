@@ -1,205 +1,13 @@
+extern crate intcode;
+
+use intcode::{StopReason, VM};
 use std::collections::VecDeque;
-use std::fs::File;
-use std::io::{self, Read};
 use std::iter;
-use std::path::Path;
-
-#[derive(Debug)]
-struct VM {
-    state: Vec<i64>,
-    pc: usize,
-    input: Vec<i64>,
-    output: Option<i64>,
-    halted: bool,
-    relative_base: i64,
-    waiting_for_input: bool,
-}
-
-impl VM {
-    fn next_value(&mut self) -> i64 {
-        let v = self.state[self.pc];
-        self.pc += 1;
-        v
-    }
-
-    fn get_params(&mut self, code: i64, num_params: i64) -> Vec<i64> {
-        let mut params = Vec::with_capacity(num_params as usize);
-        for i in 0..num_params {
-            let p = self.next_value();
-            let mode = (code % 10_i64.pow(3 + i as u32)) / (10_i64.pow(2 + i as u32));
-            match mode {
-                // Position
-                0 => params.push(self.state[p as usize]),
-                // Immediate
-                1 => params.push(p),
-                // Relative
-                2 => params.push(self.state[(p + self.relative_base) as usize]),
-                _ => panic!("Invalid mode: {}", mode),
-            }
-            // println!("\t{} => {}", p, params.last().unwrap());
-        }
-        // println!("{} {:?}", code, params);
-        params
-    }
-
-    fn get_write_location(&mut self, code: i64, num_params: i64) -> usize {
-        let mode = (code % 10_i64.pow(2 + num_params as u32)) / (10_i64.pow(1 + num_params as u32));
-        let p = self.next_value() as usize;
-        let pos = match mode {
-            // Position
-            0 => p,
-            // Relative
-            2 => ((p as i64) + self.relative_base) as usize,
-            _ => panic!("Invalid mode: {}", mode),
-        };
-        pos
-    }
-
-    fn run(&mut self) {
-        while !self.halted {
-            self.run_one();
-        }
-    }
-
-    fn run_one(&mut self) {
-        if self.halted || self.waiting_for_input {
-            return;
-        }
-
-        // println!("---");
-        // println!("instruction: {:?}", &self.state[self.pc..=self.pc+3]);
-        // println!("relative base: {}", self.relative_base);
-        let op_code = self.next_value();
-        match op_code % 100 {
-            // Add
-            1 => {
-                let params = self.get_params(op_code, 2);
-                let pos = self.get_write_location(op_code, 3);
-                self.set_state(pos, params[0] + params[1]);
-            }
-            // Mult
-            2 => {
-                let params = self.get_params(op_code, 2);
-                let pos = self.get_write_location(op_code, 3);
-                self.set_state(pos, params[0] * params[1]);
-            }
-            // Input
-            3 => {
-                let idx = self.get_write_location(op_code, 1);
-                if !self.input.is_empty() {
-                    let input = self.input.remove(0);
-                    self.set_state(idx, input);
-                } else {
-                    self.pc -= 2;
-                    self.waiting_for_input = true;
-                }
-            }
-            // Output
-            4 => {
-                let params = self.get_params(op_code, 1);
-                self.output = Some(params[0]);
-                // println!("Output: {:?}", self.output);
-            }
-            // Jump-Non-Zero
-            5 => {
-                let params = self.get_params(op_code, 2);
-                if params[0] != 0 {
-                    self.pc = params[1] as usize;
-                    // println!("jumped to {}", params[1]);
-                }
-            }
-            // Jump-Eq-Zero
-            6 => {
-                let params = self.get_params(op_code, 2);
-                if params[0] == 0 {
-                    self.pc = params[1] as usize;
-                    // println!("jumped to {}", params[1]);
-                }
-            }
-            // Less Than
-            7 => {
-                let params = self.get_params(op_code, 2);
-                let idx = self.get_write_location(op_code, 3);
-                self.set_state(idx, if params[0] < params[1] { 1 } else { 0 });
-            }
-            // Equal
-            8 => {
-                let params = self.get_params(op_code, 2);
-                let idx = self.get_write_location(op_code, 3);
-                self.set_state(idx, if params[0] == params[1] { 1 } else { 0 });
-            }
-            // Adjust relative base
-            9 => {
-                let params = self.get_params(op_code, 1);
-                self.relative_base += params[0];
-                // println!("new relative base: {}", self.relative_base);
-            }
-            // Halt
-            99 => self.halted = true,
-            _ => panic!("Unknown opcode: {}", op_code % 100),
-        }
-    }
-
-    fn run_until_output(&mut self) -> Option<i64> {
-        while !self.halted && !self.waiting_for_input {
-            self.run_one();
-            if self.output.is_some() {
-                return self.get_output();
-            }
-        }
-        None
-    }
-
-    fn get_output(&mut self) -> Option<i64> {
-        let o = self.output;
-        self.output = None;
-        return o;
-    }
-
-    fn run_until_interrupt(&mut self) {
-        while !self.halted && !self.waiting_for_input && self.output.is_none() {
-            self.run_one();
-        }
-    }
-
-    fn new(initial_state: Vec<i64>) -> VM {
-        VM {
-            state: initial_state,
-            pc: 0,
-            output: None,
-            input: Vec::new(),
-            halted: false,
-            relative_base: 0,
-            waiting_for_input: false,
-        }
-    }
-
-    pub fn set_state(&mut self, pc: usize, val: i64) {
-        self.state[pc as usize] = val;
-        // println!("set location {} to value {}", pc, val);
-    }
-
-    pub fn push_input(&mut self, input: i64) {
-        self.input.push(input);
-        self.waiting_for_input = false;
-    }
-
-    pub fn write_string(&mut self, s: String) {
-        for c in s.chars() {
-            self.push_input(c as u8 as i64);
-        }
-    }
-}
-
-fn read_file<P>(filename: P) -> io::Result<String>
-where
-    P: AsRef<Path>,
-{
-    let mut file = File::open(filename)?;
-    let mut out = String::new();
-    file.read_to_string(&mut out)?;
-    Ok(out)
-}
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Copy, Clone)]
 struct Packet {
@@ -213,6 +21,8 @@ struct Network {
     mailboxes: Vec<VecDeque<Packet>>,
     nat_packet: Option<Packet>,
     prev_packet: Option<Packet>,
+    first_nat_y: Option<i64>,
+    packets: usize,
 }
 
 impl Network {
@@ -227,28 +37,27 @@ impl Network {
 
         Network {
             vms,
-            partial_packets: iter::repeat_with(|| Vec::new()).take(num_devices).collect(),
-            mailboxes: iter::repeat_with(|| VecDeque::new())
+            partial_packets: iter::repeat_with(Vec::new).take(num_devices).collect(),
+            mailboxes: iter::repeat_with(VecDeque::new)
                 .take(num_devices)
                 .collect(),
             nat_packet: None,
             prev_packet: None,
+            first_nat_y: None,
+            packets: 0,
         }
     }
 
     fn run_one(&mut self) {
         for (idx, vm) in self.vms.iter_mut().enumerate() {
-            // if !vm.input.is_empty() {
-            //     println!("{} {} {:?}", idx, vm.halted, vm.input);
-            // }
-            vm.run_until_interrupt();
+            let stop = vm.resume().unwrap();
             if let Some(packet) = self.mailboxes[idx].pop_front() {
                 vm.push_input(packet.x);
                 vm.push_input(packet.y);
-            } else if vm.waiting_for_input {
+            } else if stop == StopReason::NeedsInput {
                 vm.push_input(-1);
             }
-            if let Some(v) = vm.get_output() {
+            if let StopReason::Output(v) = stop {
                 // println!("{:?}", self.partial_packets[idx]);
                 self.partial_packets[idx].push(v);
                 assert!(self.partial_packets[idx].len() <= 3);
@@ -260,9 +69,10 @@ impl Network {
                         y: data[2],
                     };
                     self.partial_packets[idx].clear();
+                    self.packets += 1;
                     if addr == 255 {
-                        if self.nat_packet.is_none() {
-                            println!("Part 1: {}", packet.y);
+                        if self.first_nat_y.is_none() {
+                            self.first_nat_y = Some(packet.y);
                         }
                         self.nat_packet = Some(packet);
                     } else if addr < self.mailboxes.len() {
@@ -273,20 +83,26 @@ impl Network {
         }
     }
 
+    fn instructions(&self) -> u64 {
+        self.vms.iter().map(|vm| vm.instructions_executed()).sum()
+    }
+
     fn is_idle(&self) -> bool {
         self.vms
             .iter()
-            .all(|vm| vm.input.len() == 1 && vm.input.first() == Some(&-1))
+            .all(|vm| *vm.pending_input() == [-1])
     }
 
-    pub fn run(&mut self) {
-        while self.vms.iter().any(|vm| !vm.halted) {
+    /// Runs until the NAT sends the same y twice in a row and returns the
+    /// first y sent to the NAT and that repeated y.
+    pub fn run(&mut self) -> (i64, i64) {
+        loop {
+            assert!(self.vms.iter().any(|vm| !vm.halted()), "every NIC halted");
             self.run_one();
             if self.is_idle() && self.nat_packet.is_some() {
                 if let Some(old) = self.prev_packet {
                     if self.nat_packet.unwrap().y == old.y {
-                        println!("Part 2: {}", old.y);
-                        return;
+                        return (self.first_nat_y.unwrap(), old.y);
                     }
                 }
                 self.prev_packet = self.nat_packet;
@@ -296,14 +112,204 @@ impl Network {
     }
 }
 
+
+// How many times in a row a NIC has to find its inbox empty before it counts
+// as idle and blocks until a packet arrives
+const IDLE_POLLS: usize = 2;
+
+// What the NAT needs to know to tell that the network has gone quiet
+#[derive(Default)]
+struct Activity {
+    // NICs blocked on an empty inbox
+    blocked: usize,
+    // Packets sent to a NIC but not read by it yet
+    in_flight: usize,
+    // Packets sent by NICs, including those to the NAT
+    packets: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    activity: Mutex<Activity>,
+    // Signalled whenever a NIC blocks
+    quiet: Condvar,
+    stop: AtomicBool,
+}
+
+/// The same network with every NIC on its own thread. Packets travel over
+/// channels; `None` tells a NIC to shut down.
+struct ThreadedNetwork {
+    inboxes: Vec<Sender<Option<Packet>>>,
+    nat: Receiver<Packet>,
+    shared: Arc<Shared>,
+    nics: Vec<thread::JoinHandle<u64>>,
+}
+
+impl ThreadedNetwork {
+    pub fn setup(input: Vec<i64>, num_devices: usize) -> ThreadedNetwork {
+        let (inboxes, receivers): (Vec<_>, Vec<_>) =
+            (0..num_devices).map(|_| mpsc::channel()).unzip();
+        let (to_nat, nat) = mpsc::channel();
+        let shared = Arc::new(Shared::default());
+        let nics = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(addr, inbox)| {
+                let mut vm = VM::new(input.to_vec());
+                vm.push_input(addr as i64);
+                let peers = inboxes.clone();
+                let to_nat = to_nat.clone();
+                let shared = shared.clone();
+                thread::spawn(move || nic(vm, inbox, peers, to_nat, shared))
+            })
+            .collect();
+        ThreadedNetwork {
+            inboxes,
+            nat,
+            shared,
+            nics,
+        }
+    }
+
+    /// Runs the NAT on the calling thread until it sends the same y twice in
+    /// a row. Returns the first y sent to the NAT, that repeated y, the
+    /// packets routed and the instructions every NIC executed.
+    pub fn run(self) -> (i64, i64, usize, u64) {
+        let mut first_y = None;
+        let mut last = None;
+        let mut prev_sent: Option<Packet> = None;
+        let part2 = loop {
+            {
+                let mut activity = self.shared.activity.lock().unwrap();
+                while activity.blocked < self.inboxes.len() || activity.in_flight > 0 {
+                    activity = self.shared.quiet.wait(activity).unwrap();
+                }
+                // Every NIC is waiting on an empty inbox, so whatever reached
+                // the NAT is all there is
+                for packet in self.nat.try_iter() {
+                    first_y = first_y.or(Some(packet.y));
+                    last = Some(packet);
+                }
+                let packet = last.expect("network went idle before using the NAT");
+                if prev_sent.is_some_and(|p| p.y == packet.y) {
+                    break packet.y;
+                }
+                prev_sent = Some(packet);
+                activity.in_flight += 1;
+            }
+            self.inboxes[0].send(Some(prev_sent.unwrap())).unwrap();
+        };
+
+        self.shared.stop.store(true, Ordering::SeqCst);
+        for inbox in &self.inboxes {
+            let _ = inbox.send(None);
+        }
+        let instructions = self.nics.into_iter().map(|nic| nic.join().unwrap()).sum();
+        let packets = self.shared.activity.lock().unwrap().packets;
+        (first_y.unwrap(), part2, packets, instructions)
+    }
+}
+
+// Runs one NIC until the network shuts down and returns the instructions it
+// executed
+fn nic(
+    mut vm: VM,
+    inbox: Receiver<Option<Packet>>,
+    peers: Vec<Sender<Option<Packet>>>,
+    to_nat: Sender<Packet>,
+    shared: Arc<Shared>,
+) -> u64 {
+    let mut empty_polls = 0;
+    loop {
+        match vm.resume().unwrap() {
+            StopReason::NeedsInput => {
+                let message = if empty_polls < IDLE_POLLS {
+                    match inbox.try_recv() {
+                        Ok(message) => Some(message),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => break,
+                    }
+                } else {
+                    shared.activity.lock().unwrap().blocked += 1;
+                    shared.quiet.notify_one();
+                    let message = inbox.recv().unwrap_or(None);
+                    shared.activity.lock().unwrap().blocked -= 1;
+                    Some(message)
+                };
+                match message {
+                    Some(Some(packet)) => {
+                        empty_polls = 0;
+                        vm.push_input(packet.x);
+                        vm.push_input(packet.y);
+                        shared.activity.lock().unwrap().in_flight -= 1;
+                    }
+                    Some(None) => break,
+                    None if shared.stop.load(Ordering::Relaxed) => break,
+                    None => {
+                        empty_polls += 1;
+                        vm.push_input(-1);
+                    }
+                }
+            }
+            StopReason::Output(addr) => {
+                empty_polls = 0;
+                let mut next = || match vm.resume().unwrap() {
+                    StopReason::Output(v) => v,
+                    stop => panic!("NIC stopped mid packet: {:?}", stop),
+                };
+                let packet = Packet {
+                    x: next(),
+                    y: next(),
+                };
+                let addr = addr as usize;
+                if addr == 255 {
+                    shared.activity.lock().unwrap().packets += 1;
+                    to_nat.send(packet).unwrap();
+                } else if addr < peers.len() {
+                    {
+                        let mut activity = shared.activity.lock().unwrap();
+                        activity.in_flight += 1;
+                        activity.packets += 1;
+                    }
+                    peers[addr].send(Some(packet)).unwrap();
+                }
+            }
+            StopReason::Halted => break,
+            stop => panic!("NIC stopped unexpectedly: {:?}", stop),
+        }
+    }
+    vm.instructions_executed()
+}
+
+fn throughput(name: &str, elapsed: Duration, packets: usize, instructions: u64) {
+    let secs = elapsed.as_secs_f64();
+    eprintln!(
+        "{}: {:.1} ms, {} packets ({:.0}/s), {} instructions ({:.1} M/s)",
+        name,
+        secs * 1000.0,
+        packets,
+        packets as f64 / secs,
+        instructions,
+        instructions as f64 / secs / 1e6
+    );
+}
+
 pub fn main() {
-    let mut input: Vec<i64> = read_file("23.txt")
-        .expect("file doesn't exist")
-        .split(",")
-        .map(|x| x.trim().parse().unwrap())
-        .collect();
-    input.extend(iter::repeat(0).take(10000));
+    let input = intcode::read_program("23.txt").expect("file doesn't exist");
+
+    let start = Instant::now();
+    let mut network = Network::setup(input.to_vec(), 50);
+    let (part1, part2) = network.run();
+    let sequential = start.elapsed();
+
+    let start = Instant::now();
+    let (threaded_part1, threaded_part2, packets, instructions) =
+        ThreadedNetwork::setup(input, 50).run();
+    let threaded = start.elapsed();
+    assert_eq!((part1, part2), (threaded_part1, threaded_part2));
 
-    let mut network = Network::setup(input, 50);
-    network.run();
+    println!("Part 1: {}", part1);
+    println!("Part 2: {}", part2);
+    throughput("sequential", sequential, network.packets, network.instructions());
+    throughput("threaded", threaded, packets, instructions);
 }
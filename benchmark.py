@@ -18,7 +18,7 @@ languages = {
     # 'swift': 'Swift',
     # 'java': 'Java',
     # 'js': 'Javascript',
-    "rs": ("rustc -O {0} -o {1}", "./{0}", "Rust"),
+    "rs": ("rustc -O -L ../target {0} -o {1}", "./{0}", "Rust"),
     "go": ("go build -o {1} {0}", "./{}", "Go"),
 }
 
@@ -95,6 +95,10 @@ def benchmark_day(day):
 
 
 if __name__ == "__main__":
+    # Intcode days link against the shared interpreter
+    if os.system("make intcode") != 0:
+        raise Exception("Failed to build intcode library")
+
     if len(sys.argv) == 2:
         benchmark_day(int(sys.argv[-1]))
     else:
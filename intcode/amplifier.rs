@@ -0,0 +1,283 @@
+//! Chains of amplifiers and the search for their best phase settings.
+//!
+//! Each amplifier runs its own copy of the same program. It first reads its
+//! phase setting, then signals: the first amplifier gets a 0, every other one
+//! whatever the amplifier before it outputs. With `Wiring::Feedback` the last
+//! amplifier's outputs are also fed back into the first. Either way, the
+//! signal is the last value the last amplifier outputs.
+//!
+//! `search` tries every ordering of a set of phases and returns the one with
+//! the strongest signal:
+//!
+//! ```no_run
+//! use intcode::amplifier::{self, Wiring};
+//!
+//! let program = intcode::read_program("7.txt").unwrap();
+//! let best = amplifier::search(&program, &[5, 6, 7, 8, 9], 5, Wiring::Feedback).unwrap();
+//! println!("{:?} gives {}", best.phases, best.signal);
+//! ```
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::thread;
+
+use crate::error::VmError;
+use crate::exec::{channel, Executor};
+use crate::vm::{StopReason, VM};
+
+/// How the amplifiers are connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wiring {
+    /// Each amplifier feeds the next and the last one's output is the signal.
+    Chain,
+    /// Like `Chain`, but the last amplifier also feeds the first, until they
+    /// all halt.
+    Feedback,
+}
+
+/// The strongest signal found and the phases that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Best {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// An amplifier (zero based) faulted.
+    Vm {
+        phases: Vec<i64>,
+        amplifier: usize,
+        error: VmError,
+    },
+    /// An amplifier wanted input that was never going to come.
+    Stuck { phases: Vec<i64>, amplifier: usize },
+    /// The last amplifier halted without outputting anything.
+    NoSignal { phases: Vec<i64> },
+    /// More amplifiers than phases to choose from, or none at all.
+    Amplifiers { amplifiers: usize, phases: usize },
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchError::Vm {
+                phases,
+                amplifier,
+                error,
+            } => write!(f, "amplifier {} with phases {:?}: {}", amplifier, phases, error),
+            SearchError::Stuck { phases, amplifier } => write!(
+                f,
+                "amplifier {} with phases {:?} is stuck waiting for input",
+                amplifier, phases
+            ),
+            SearchError::NoSignal { phases } => {
+                write!(f, "no signal with phases {:?}", phases)
+            }
+            SearchError::Amplifiers { amplifiers, phases } => write!(
+                f,
+                "can't choose phases for {} amplifiers from {} phases",
+                amplifiers, phases
+            ),
+        }
+    }
+}
+
+impl Error for SearchError {}
+
+/// Runs one amplifier per phase, in order, and returns the signal.
+pub fn signal(program: &[i64], phases: &[i64], wiring: Wiring) -> Result<i64, SearchError> {
+    evaluate(&mut VM::new(program.to_vec()), phases, wiring)
+}
+
+/// Tries every ordering of `amplifiers` phases picked from `alphabet` and
+/// returns the one with the strongest signal.
+///
+/// Orderings are split between all CPU cores. If several give the same
+/// signal, the one enumerated first wins, so the result doesn't depend on
+/// how the work was split. Any error is reported the same way: the first
+/// ordering that fails, in enumeration order.
+pub fn search(
+    program: &[i64],
+    alphabet: &[i64],
+    amplifiers: usize,
+    wiring: Wiring,
+) -> Result<Best, SearchError> {
+    if amplifiers == 0 || amplifiers > alphabet.len() {
+        return Err(SearchError::Amplifiers {
+            amplifiers,
+            phases: alphabet.len(),
+        });
+    }
+    let orderings: Vec<Vec<i64>> = combinations(alphabet, amplifiers)
+        .into_iter()
+        .flat_map(|c| permutations(&c))
+        .collect();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = orderings.len().div_ceil(threads);
+
+    // Best signal of each chunk as (signal, index into `orderings`)
+    let results: Vec<Result<Option<(i64, usize)>, SearchError>> = thread::scope(|scope| {
+        let workers: Vec<_> = orderings
+            .chunks(chunk)
+            .enumerate()
+            .map(|(n, orderings)| {
+                scope.spawn(move || {
+                    // Every amplifier is forked from here, so they share its
+                    // pages instead of copying the program
+                    let mut template = VM::new(program.to_vec());
+                    let mut best: Option<(i64, usize)> = None;
+                    for (i, phases) in orderings.iter().enumerate() {
+                        let signal = evaluate(&mut template, phases, wiring)?;
+                        if best.is_none_or(|(s, _)| signal > s) {
+                            best = Some((signal, n * chunk + i));
+                        }
+                    }
+                    Ok(best)
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+
+    let mut best: Option<(i64, usize)> = None;
+    for result in results {
+        if let Some((signal, index)) = result? {
+            if best.is_none_or(|(s, _)| signal > s) {
+                best = Some((signal, index));
+            }
+        }
+    }
+    let (signal, index) = best.expect("at least one ordering");
+    Ok(Best {
+        phases: orderings[index].clone(),
+        signal,
+    })
+}
+
+// Runs the amplifiers as tasks connected by channels, each forked from
+// `template`
+fn evaluate(template: &mut VM, phases: &[i64], wiring: Wiring) -> Result<i64, SearchError> {
+    let n = phases.len();
+    let (senders, receivers): (Vec<_>, Vec<_>) = phases
+        .iter()
+        .map(|p| {
+            let (tx, rx) = channel();
+            tx.send(*p);
+            (tx, rx)
+        })
+        .unzip();
+    senders[0].send(0);
+    // The last amplifier writes to a tap that records the signal and, with
+    // feedback, passes it on to the first
+    let (to_tap, tap) = channel();
+    let signal = Cell::new(None);
+
+    let mut vms: Vec<VM> = phases.iter().map(|_| template.fork()).collect();
+    let (last, rest) = vms.split_last_mut().unwrap();
+    let mut executor = Executor::new();
+    for (idx, vm) in rest.iter_mut().enumerate() {
+        executor.spawn(vm.run_async(&receivers[idx], &senders[idx + 1]));
+    }
+    let last_input = &receivers[n - 1];
+    executor.spawn(async move {
+        // Owning `to_tap` closes the tap once the last amplifier is done
+        let to_tap = to_tap;
+        last.run_async(last_input, &to_tap).await
+    });
+    executor.spawn(async {
+        while let Some(v) = tap.recv().await {
+            signal.set(Some(v));
+            if wiring == Wiring::Feedback {
+                senders[0].send(v);
+            }
+        }
+        Ok(StopReason::Halted)
+    });
+
+    for (amplifier, result) in executor.run().into_iter().enumerate().take(n) {
+        match result {
+            Some(Ok(_)) => {}
+            Some(Err(error)) => {
+                return Err(SearchError::Vm {
+                    phases: phases.to_vec(),
+                    amplifier,
+                    error,
+                })
+            }
+            None => {
+                return Err(SearchError::Stuck {
+                    phases: phases.to_vec(),
+                    amplifier,
+                })
+            }
+        }
+    }
+    signal.get().ok_or_else(|| SearchError::NoSignal {
+        phases: phases.to_vec(),
+    })
+}
+
+/// Every ordering of `items`, generated with Heap's algorithm so that each
+/// one differs from the previous by a single swap.
+pub fn permutations<T: Clone>(items: &[T]) -> Permutations<T> {
+    Permutations {
+        items: items.to_vec(),
+        counters: vec![0; items.len()],
+        i: 1,
+        first: true,
+    }
+}
+
+/// Iterator returned by `permutations`.
+#[derive(Debug, Clone)]
+pub struct Permutations<T> {
+    items: Vec<T>,
+    // Heap's algorithm's stack state, unrolled into a loop
+    counters: Vec<usize>,
+    i: usize,
+    first: bool,
+}
+
+impl<T: Clone> Iterator for Permutations<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        if self.first {
+            self.first = false;
+            return Some(self.items.clone());
+        }
+        while self.i < self.items.len() {
+            let i = self.i;
+            if self.counters[i] < i {
+                if i.is_multiple_of(2) {
+                    self.items.swap(0, i);
+                } else {
+                    self.items.swap(self.counters[i], i);
+                }
+                self.counters[i] += 1;
+                self.i = 1;
+                return Some(self.items.clone());
+            }
+            self.counters[i] = 0;
+            self.i += 1;
+        }
+        None
+    }
+}
+
+// Every way to pick `k` of `items`, keeping their order
+fn combinations(items: &[i64], k: usize) -> Vec<Vec<i64>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    let mut out = Vec::new();
+    for (i, item) in items.iter().enumerate().take(items.len() + 1 - k) {
+        for mut rest in combinations(&items[i + 1..], k - 1) {
+            rest.insert(0, *item);
+            out.push(rest);
+        }
+    }
+    out
+}
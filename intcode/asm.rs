@@ -0,0 +1,292 @@
+//! Assembles Intcode from the same syntax the disassembler prints.
+//!
+//! ```text
+//! ; echo until a zero is read
+//! loop:   IN -> [value]
+//!         OUT [value]
+//!         JNZ [value], #loop
+//!         HLT
+//! value:  DATA 0
+//! greeting: STRING "hi\n"
+//! ```
+//!
+//! Operands are `#n` (immediate), `[n]` (position) or `[rb+n]` (relative),
+//! where `n` is a number, a label, or a label plus or minus a number. The
+//! written operand goes after `->`. A leading `0042:` address, as printed by
+//! the disassembler, is ignored, so listings assemble back to the same program.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::{Instruction, Mode, Opcode, Param};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// One based source line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// A number, or a label to be resolved once every label's address is known
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Label(String, i64),
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction(Opcode, Vec<(Mode, Expr)>),
+    Data(Vec<Expr>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction(_, params) => 1 + params.len(),
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+/// Assembles `source` into a program the VM can load.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut items: Vec<(usize, Item)> = Vec::new();
+    let mut addr = 0;
+
+    for (idx, raw) in source.lines().enumerate() {
+        let line = idx + 1;
+        let err = |message: String| AsmError { line, message };
+        let mut rest = strip_comment(raw).trim();
+
+        // Address from a disassembler listing, or labels
+        while let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim();
+            if name.is_empty() || name.contains(char::is_whitespace) || name.contains('"') {
+                break;
+            }
+            if !name.chars().all(|c| c.is_ascii_digit()) {
+                if !is_identifier(name) {
+                    return Err(err(format!("invalid label `{}`", name)));
+                }
+                if labels.insert(name.to_string(), addr).is_some() {
+                    return Err(err(format!("duplicate label `{}`", name)));
+                }
+            }
+            rest = rest[colon + 1..].trim_start();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (word, args) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        };
+        let directive = word.trim_start_matches('.');
+        let item = if directive.eq_ignore_ascii_case("data") {
+            let values = split_operands(args)
+                .iter()
+                .map(|s| parse_expr(s))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(err)?;
+            if values.is_empty() {
+                return Err(err("DATA needs at least one value".to_string()));
+            }
+            Item::Data(values)
+        } else if directive.eq_ignore_ascii_case("string") {
+            let text = parse_string(args).map_err(err)?;
+            Item::Data(text.bytes().map(|b| Expr::Number(i64::from(b))).collect())
+        } else {
+            let opcode = Opcode::from_mnemonic(word)
+                .ok_or_else(|| err(format!("unknown mnemonic `{}`", word)))?;
+            Item::Instruction(opcode, parse_operands(opcode, args).map_err(err)?)
+        };
+        addr += item.size();
+        items.push((line, item));
+    }
+
+    let mut program = Vec::with_capacity(addr);
+    for (line, item) in items {
+        let resolve = |e: &Expr| match e {
+            Expr::Number(n) => Ok(*n),
+            Expr::Label(name, offset) => labels
+                .get(name)
+                .map(|a| *a as i64 + offset)
+                .ok_or_else(|| AsmError {
+                    line,
+                    message: format!("undefined label `{}`", name),
+                }),
+        };
+        match item {
+            Item::Instruction(opcode, params) => {
+                let params = params
+                    .iter()
+                    .map(|(mode, e)| {
+                        resolve(e).map(|value| Param { mode: *mode, value })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                program.extend(Instruction { opcode, params }.encode());
+            }
+            Item::Data(values) => {
+                for v in &values {
+                    program.push(resolve(v)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+// Drops a trailing `; comment`, leaving semicolons inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn split_operands(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').map(|p| p.trim()).collect()
+    }
+}
+
+fn parse_operands(opcode: Opcode, args: &str) -> Result<Vec<(Mode, Expr)>, String> {
+    // The written operand may come after `->` or just be the last one
+    let (reads, write) = match args.find("->") {
+        Some(i) => (&args[..i], Some(args[i + 2..].trim())),
+        None => (args, None),
+    };
+    let mut operands: Vec<&str> = split_operands(reads);
+    if let Some(w) = write {
+        if !opcode.writes() {
+            return Err(format!("{} doesn't write to memory", opcode.mnemonic()));
+        }
+        operands.push(w);
+    }
+    if operands.len() != opcode.num_params() {
+        return Err(format!(
+            "{} takes {} operands, found {}",
+            opcode.mnemonic(),
+            opcode.num_params(),
+            operands.len()
+        ));
+    }
+
+    let mut params = Vec::with_capacity(operands.len());
+    for (i, op) in operands.iter().enumerate() {
+        let (mode, e) = parse_operand(op)?;
+        if mode == Mode::Immediate && opcode.writes() && i == operands.len() - 1 {
+            return Err(format!("can't write to immediate operand `{}`", op));
+        }
+        params.push((mode, e));
+    }
+    Ok(params)
+}
+
+fn parse_operand(s: &str) -> Result<(Mode, Expr), String> {
+    if let Some(value) = s.strip_prefix('#') {
+        return Ok((Mode::Immediate, parse_expr(value)?));
+    }
+    let inner = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or_else(|| format!("expected `#n`, `[n]` or `[rb+n]`, found `{}`", s))?
+        .trim();
+    match inner.strip_prefix("rb") {
+        Some(offset) if !offset.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') => {
+            let offset = offset.trim();
+            if offset.is_empty() {
+                Ok((Mode::Relative, Expr::Number(0)))
+            } else if let Some(o) = offset.strip_prefix('+') {
+                Ok((Mode::Relative, parse_expr(o)?))
+            } else if offset.starts_with('-') {
+                Ok((Mode::Relative, parse_expr(offset)?))
+            } else {
+                Err(format!("invalid relative operand `{}`", s))
+            }
+        }
+        _ => Ok((Mode::Position, parse_expr(inner)?)),
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse() {
+        return Ok(Expr::Number(n));
+    }
+    // `label`, `label+n` or `label-n`
+    let (name, offset) = match s.rfind(['+', '-']) {
+        Some(i) if i > 0 => {
+            let offset: i64 = s[i + 1..]
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid offset in `{}`", s))?;
+            let sign = if &s[i..=i] == "-" { -1 } else { 1 };
+            (s[..i].trim(), sign * offset)
+        }
+        _ => (s, 0),
+    };
+    if is_identifier(name) {
+        Ok(Expr::Label(name.to_string(), offset))
+    } else {
+        Err(format!("invalid value `{}`", s))
+    }
+}
+
+fn parse_string(s: &str) -> Result<String, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| "STRING needs a quoted string".to_string())?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            other => return Err(format!("invalid escape `\\{}`", other.unwrap_or(' '))),
+        }
+    }
+    if !out.is_ascii() {
+        return Err("STRING must be ASCII".to_string());
+    }
+    Ok(out)
+}
@@ -0,0 +1,490 @@
+//! Intcode with words of any size.
+//!
+//! `BigVm` runs programs whose values don't fit in an `i64`, using the
+//! `BigInt` word type defined here. Addresses, jump targets, opcodes and the
+//! relative base still have to fit in an `i64`; a value that doesn't faults
+//! with `ErrorKind::Overflow`. It has none of `VM`'s extras (engines, hooks,
+//! budgets) and is much slower, so use it when the answer doesn't fit, not by
+//! default.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::ops::{Add, Mul, Neg};
+use std::str::FromStr;
+
+use crate::error::{ErrorKind, VmError};
+use crate::instruction::mode_digit;
+use crate::vm::StopReason;
+
+/// An arbitrary precision integer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    // Base 2^32 digits, least significant first, without trailing zeros.
+    // Zero has no digits and is never negative.
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> BigInt {
+        BigInt::default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0u64, |acc, d| (acc << 32) | u64::from(*d));
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    fn from_parts(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    // Multiplies the magnitude by `m` and adds `a`, in place
+    fn mul_add_small(&mut self, m: u32, a: u32) {
+        let mut carry = u64::from(a);
+        for d in &mut self.digits {
+            let v = u64::from(*d) * u64::from(m) + carry;
+            *d = v as u32;
+            carry = v >> 32;
+        }
+        if carry > 0 {
+            self.digits.push(carry as u32);
+        }
+    }
+
+    // Divides the magnitude by `m` in place and returns the remainder
+    fn div_small(&mut self, m: u32) -> u32 {
+        let mut rem = 0u64;
+        for d in self.digits.iter_mut().rev() {
+            let v = (rem << 32) | u64::from(*d);
+            *d = (v / u64::from(m)) as u32;
+            rem = v % u64::from(m);
+        }
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+        rem as u32
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, d) in long.iter().enumerate() {
+        let v = u64::from(*d) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        out.push(v as u32);
+        carry = v >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+// `a - b` for `a >= b`
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, d) in a.iter().enumerate() {
+        let mut v = i64::from(*d) - i64::from(b.get(i).copied().unwrap_or(0)) - borrow;
+        borrow = if v < 0 {
+            v += 1 << 32;
+            1
+        } else {
+            0
+        };
+        out.push(v as u32);
+    }
+    out
+}
+
+impl From<i64> for BigInt {
+    fn from(v: i64) -> BigInt {
+        let magnitude = v.unsigned_abs();
+        BigInt::from_parts(v < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_magnitude(&self.digits, &other.digits));
+        }
+        match cmp_magnitude(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, sub_magnitude(&other.digits, &self.digits))
+            }
+            _ => BigInt::from_parts(self.negative, sub_magnitude(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut out = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in other.digits.iter().enumerate() {
+                let v = u64::from(*a) * u64::from(*b) + u64::from(out[i + j]) + carry;
+                out[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            out[i + other.digits.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, out)
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        let negative = !self.negative;
+        BigInt::from_parts(negative, self.digits)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.digits, &other.digits),
+            (true, true) => cmp_magnitude(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BigInt, String> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid number `{}`", s));
+        }
+        let mut n = BigInt::zero();
+        for b in digits.bytes() {
+            n.mul_add_small(10, u32::from(b - b'0'));
+        }
+        Ok(BigInt::from_parts(negative, n.digits))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Nine decimal digits at a time, least significant first
+        let mut n = self.clone();
+        let mut chunks = Vec::new();
+        while !n.is_zero() {
+            chunks.push(n.div_small(1_000_000_000));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.last().unwrap())?;
+        for chunk in chunks.iter().rev().skip(1) {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a comma separated Intcode program with values of any size.
+pub fn parse_program(source: &str) -> io::Result<Vec<BigInt>> {
+    source
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+// Addresses below this are kept in a flat table, anything above is sparse
+const DENSE_WORDS: usize = 1 << 16;
+
+/// An Intcode machine whose words are `BigInt`s.
+#[derive(Debug, Clone)]
+pub struct BigVm {
+    memory: Vec<BigInt>,
+    sparse: HashMap<usize, BigInt>,
+    pc: usize,
+    relative_base: i64,
+    input: VecDeque<BigInt>,
+    output: Vec<BigInt>,
+    halted: bool,
+    // Address and raw value of the instruction being executed
+    instruction_pc: usize,
+    instruction: i64,
+}
+
+impl BigVm {
+    pub fn new(program: Vec<BigInt>) -> BigVm {
+        BigVm {
+            memory: program,
+            sparse: HashMap::new(),
+            pc: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            halted: false,
+            instruction_pc: 0,
+            instruction: 0,
+        }
+    }
+
+    pub fn push_input<T: Into<BigInt>>(&mut self, input: T) {
+        self.input.push_back(input.into());
+    }
+
+    pub fn take_output(&mut self) -> Vec<BigInt> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn get_state(&self, addr: usize) -> BigInt {
+        match self.memory.get(addr) {
+            Some(v) => v.clone(),
+            None => self.sparse.get(&addr).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Every non-zero word as (address, value), lowest address first.
+    pub fn nonzero_words(&self) -> Vec<(usize, BigInt)> {
+        let mut words: Vec<(usize, BigInt)> = self
+            .memory
+            .iter()
+            .cloned()
+            .enumerate()
+            .chain(self.sparse.iter().map(|(a, v)| (*a, v.clone())))
+            .filter(|(_, v)| !v.is_zero())
+            .collect();
+        words.sort_by_key(|(addr, _)| *addr);
+        words
+    }
+
+    pub fn set_state(&mut self, addr: usize, val: BigInt) {
+        if addr < self.memory.len() {
+            self.memory[addr] = val;
+        } else if addr < DENSE_WORDS {
+            self.memory.resize(addr + 1, BigInt::zero());
+            self.memory[addr] = val;
+        } else {
+            self.sparse.insert(addr, val);
+        }
+    }
+
+    /// Runs until the program halts or needs input, buffering outputs for
+    /// `take_output`.
+    pub fn run(&mut self) -> Result<StopReason, VmError> {
+        loop {
+            match self.resume()? {
+                StopReason::Output(_) => {}
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    /// Runs until the program halts, needs input or outputs a value. The
+    /// value itself is pushed onto the output buffer, since it may not fit
+    /// in `StopReason::Output`, which then carries its `i64` value or
+    /// `i64::MAX`/`i64::MIN` by sign.
+    ///
+    /// If an instruction faults, the VM is left pointing at it.
+    pub fn resume(&mut self) -> Result<StopReason, VmError> {
+        self.resume_for(u64::MAX)
+    }
+
+    /// Like `resume`, but stops with `StopReason::BudgetExhausted` after
+    /// `max_steps` instructions.
+    pub fn resume_for(&mut self, max_steps: u64) -> Result<StopReason, VmError> {
+        let mut steps = 0;
+        while !self.halted {
+            if steps == max_steps {
+                return Ok(StopReason::BudgetExhausted);
+            }
+            steps += 1;
+            self.instruction_pc = self.pc;
+            let result = self.step();
+            if result.is_err() {
+                self.pc = self.instruction_pc;
+            }
+            if let Some(reason) = result? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Halted)
+    }
+
+    fn fault(&self, kind: ErrorKind) -> VmError {
+        VmError {
+            kind,
+            pc: self.instruction_pc,
+            opcode: self.instruction,
+            relative_base: self.relative_base,
+        }
+    }
+
+    fn word(&self, v: &BigInt) -> Result<i64, VmError> {
+        v.to_i64().ok_or_else(|| self.fault(ErrorKind::Overflow))
+    }
+
+    fn address(&self, addr: i64) -> Result<usize, VmError> {
+        if addr < 0 {
+            Err(self.fault(ErrorKind::NegativeAddress(addr)))
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    fn next_value(&mut self) -> BigInt {
+        let v = self.get_state(self.pc);
+        self.pc += 1;
+        v
+    }
+
+    // Reads parameter `i` (zero based) of the instruction `code`
+    fn param(&mut self, code: i64, i: usize) -> Result<BigInt, VmError> {
+        let p = self.next_value();
+        match mode_digit(code, i) {
+            0 => Ok(self.get_state(self.address(self.word(&p)?)?)),
+            1 => Ok(p),
+            2 => {
+                let addr = self.word(&(&p + &BigInt::from(self.relative_base)))?;
+                Ok(self.get_state(self.address(addr)?))
+            }
+            mode => Err(self.fault(ErrorKind::InvalidMode { param: i, mode })),
+        }
+    }
+
+    // Resolves parameter `i` of the instruction `code` to the address it
+    // writes to
+    fn write_location(&mut self, code: i64, i: usize) -> Result<usize, VmError> {
+        let p = self.next_value();
+        match mode_digit(code, i) {
+            0 => self.address(self.word(&p)?),
+            2 => self.address(self.word(&(&p + &BigInt::from(self.relative_base)))?),
+            mode => Err(self.fault(ErrorKind::InvalidMode { param: i, mode })),
+        }
+    }
+
+    fn step(&mut self) -> Result<Option<StopReason>, VmError> {
+        let word = self.next_value();
+        let code = self.word(&word)?;
+        self.instruction = code;
+        match code % 100 {
+            1 | 2 => {
+                let a = self.param(code, 0)?;
+                let b = self.param(code, 1)?;
+                let pos = self.write_location(code, 2)?;
+                self.set_state(pos, if code % 100 == 1 { &a + &b } else { &a * &b });
+            }
+            3 => {
+                let pos = self.write_location(code, 0)?;
+                match self.input.pop_front() {
+                    Some(v) => self.set_state(pos, v),
+                    None => {
+                        self.pc = self.instruction_pc;
+                        return Ok(Some(StopReason::NeedsInput));
+                    }
+                }
+            }
+            4 => {
+                let value = self.param(code, 0)?;
+                let small = value.to_i64().unwrap_or(if value.negative {
+                    i64::MIN
+                } else {
+                    i64::MAX
+                });
+                self.output.push(value);
+                return Ok(Some(StopReason::Output(small)));
+            }
+            5 | 6 => {
+                let value = self.param(code, 0)?;
+                let target = self.param(code, 1)?;
+                if value.is_zero() == (code % 100 == 6) {
+                    self.pc = self.address(self.word(&target)?)?;
+                }
+            }
+            7 | 8 => {
+                let a = self.param(code, 0)?;
+                let b = self.param(code, 1)?;
+                let pos = self.write_location(code, 2)?;
+                let holds = if code % 100 == 7 { a < b } else { a == b };
+                self.set_state(pos, BigInt::from(holds as i64));
+            }
+            9 => {
+                let delta = self.param(code, 0)?;
+                let base = &BigInt::from(self.relative_base) + &delta;
+                self.relative_base = self.word(&base)?;
+            }
+            99 => {
+                self.halted = true;
+                return Ok(Some(StopReason::Halted));
+            }
+            _ => return Err(self.fault(ErrorKind::UnknownOpcode)),
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,38 @@
+//! Assembles an Intcode source file into a comma separated program.
+//!
+//! Usage: intcode-asm <source.asm> [-o <program.txt>]
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (source_path, out_path) = match args.as_slice() {
+        [src] => (src, None),
+        [src, flag, out] if flag == "-o" => (src, Some(out)),
+        _ => {
+            eprintln!("usage: intcode-asm <source.asm> [-o <program.txt>]");
+            process::exit(2);
+        }
+    };
+
+    let source = fs::read_to_string(source_path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source_path, e);
+        process::exit(1);
+    });
+    let program = intcode::asm::assemble(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source_path, e);
+        process::exit(1);
+    });
+
+    let words: Vec<String> = program.iter().map(|w| w.to_string()).collect();
+    let text = words.join(",") + "\n";
+    match out_path {
+        Some(path) => fs::write(path, text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }),
+        None => print!("{}", text),
+    }
+}
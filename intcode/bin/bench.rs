@@ -0,0 +1,207 @@
+//! Times both engines on the Intcode days that run the most instructions.
+//!
+//! Usage: intcode-bench [--runs N] <13.txt> <19.txt> <23.txt>
+//!
+//! Plays day13's game to the end, scans day19's tractor beam and finds its
+//! 100x100 square, and runs day23's network until the NAT repeats itself,
+//! each on a single thread. Every workload is run `--runs` times (21 by
+//! default) per engine, alternating between the engines so that a noisy
+//! stretch hits both, and the median run counts. Exits with status 1 if the
+//! engines disagree on an answer.
+
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+use intcode::{Engine, StopReason, VM};
+
+const USAGE: &str = "usage: intcode-bench [--runs N] <13.txt> <19.txt> <23.txt>";
+
+const ENGINES: [Engine; 2] = [Engine::Interpreter, Engine::Cached];
+
+type Workload = fn(&[i64], Engine) -> String;
+
+const WORKLOADS: [(&str, Workload); 3] = [
+    ("day13", arcade),
+    ("day19", tractor_beam),
+    ("day23", network),
+];
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut runs = 21;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => {
+                runs = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|n| *n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            _ if !arg.starts_with('-') => paths.push(arg),
+            _ => usage(),
+        }
+    }
+    if paths.len() != WORKLOADS.len() {
+        usage();
+    }
+
+    println!(
+        "{:<8} {:>12} {:>12} {:>8}",
+        "workload", "interpreter", "cached", "speedup"
+    );
+    let mut agree = true;
+    for ((name, workload), path) in WORKLOADS.iter().zip(&paths) {
+        let program = intcode::read_program(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        });
+        let (slow, fast, expected, answer) = median(runs, |engine| workload(&program, engine));
+        println!(
+            "{:<8} {:>9.1} ms {:>9.1} ms {:>7.2}x",
+            name,
+            slow.as_secs_f64() * 1000.0,
+            fast.as_secs_f64() * 1000.0,
+            slow.as_secs_f64() / fast.as_secs_f64()
+        );
+        if answer != expected {
+            eprintln!(
+                "{}: interpreter answered {}, cached {}",
+                name, expected, answer
+            );
+            agree = false;
+        }
+    }
+    if !agree {
+        process::exit(1);
+    }
+}
+
+// The median of `runs` runs of `f` on the interpreter and on the cached
+// engine, and what each returned
+fn median<F>(runs: usize, mut f: F) -> (Duration, Duration, String, String)
+where
+    F: FnMut(Engine) -> String,
+{
+    let mut times = [Vec::new(), Vec::new()];
+    let mut answers = [String::new(), String::new()];
+    for _ in 0..runs {
+        for (i, &engine) in ENGINES.iter().enumerate() {
+            let start = Instant::now();
+            answers[i] = f(engine);
+            times[i].push(start.elapsed());
+        }
+    }
+    for t in &mut times {
+        t.sort();
+    }
+    let [slow, fast] = times;
+    let [expected, answer] = answers;
+    (slow[runs / 2], fast[runs / 2], expected, answer)
+}
+
+fn boot(program: &[i64], engine: Engine) -> VM {
+    let mut vm = VM::new(program.to_vec());
+    vm.set_engine(engine);
+    vm
+}
+
+// Day 13: plays the game, following the ball with the paddle
+fn arcade(program: &[i64], engine: Engine) -> String {
+    let mut vm = boot(program, engine);
+    vm.set_state(0, 2);
+    let (mut ball, mut paddle, mut score) = (0, 0, 0);
+    loop {
+        let stop = vm.run().unwrap();
+        for tile in vm.take_output().chunks(3) {
+            match *tile {
+                [-1, 0, s] => score = s,
+                [x, _, 3] => paddle = x,
+                [x, _, 4] => ball = x,
+                _ => {}
+            }
+        }
+        if stop == StopReason::Halted {
+            return score.to_string();
+        }
+        vm.push_input((ball - paddle).signum());
+    }
+}
+
+// Day 19: counts the pulled points of the 50x50 grid, then walks down the
+// beam to the first 100x100 square that fits
+fn tractor_beam(program: &[i64], engine: Engine) -> String {
+    let mut drone = boot(program, engine);
+    drone.run().unwrap();
+    let mut pulled = |x: i64, y: i64| {
+        let mut vm = drone.fork();
+        vm.push_input(x);
+        vm.push_input(y);
+        vm.run().unwrap();
+        vm.take_output() == [1]
+    };
+    let grid = (0..50).flat_map(|x| (0..50).map(move |y| (x, y)));
+    let part1 = grid.filter(|&(x, y)| pulled(x, y)).count();
+    let (mut x, mut y) = (0, 0);
+    while !pulled(x + 99, y) {
+        y += 1;
+        while !pulled(x, y + 99) {
+            x += 1;
+        }
+    }
+    format!("{} {}", part1, x * 10000 + y)
+}
+
+// Day 23: runs the NICs round robin until the NAT sends the same y twice in
+// a row
+fn network(program: &[i64], engine: Engine) -> String {
+    let mut nics: Vec<VM> = (0..50)
+        .map(|addr| {
+            let mut vm = boot(program, engine);
+            vm.push_input(addr);
+            vm
+        })
+        .collect();
+    let mut nat = None;
+    let mut first_y = None;
+    let mut last_y = None;
+    loop {
+        let mut idle = true;
+        for i in 0..nics.len() {
+            if nics[i].pending_input().is_empty() {
+                nics[i].push_input(-1);
+            } else {
+                idle = false;
+            }
+            nics[i].run().unwrap();
+            for packet in nics[i].take_output().chunks(3) {
+                idle = false;
+                match packet[0] {
+                    255 => {
+                        first_y = first_y.or(Some(packet[2]));
+                        nat = Some((packet[1], packet[2]));
+                    }
+                    addr => {
+                        nics[addr as usize].push_input(packet[1]);
+                        nics[addr as usize].push_input(packet[2]);
+                    }
+                }
+            }
+        }
+        if let (true, Some((x, y))) = (idle, nat) {
+            if last_y == Some(y) {
+                return format!("{} {}", first_y.unwrap(), y);
+            }
+            last_y = Some(y);
+            nics[0].push_input(x);
+            nics[0].push_input(y);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,86 @@
+//! Writes the control flow graph of an Intcode program as Graphviz DOT.
+//!
+//! Usage: intcode-cfg [--run [-i <inputs>] [-a <text>] [--max-steps N]]
+//!                    [-o <graph.dot>] <program.txt>
+//!
+//! Without `--run` only jumps to immediate targets are followed. With it the
+//! program is run first, with `-i` and `-a` queueing input as for
+//! intcode-profile. Every jump it takes and every instruction it runs is
+//! added to the graph, which is built from memory as the run left it so code
+//! the program wrote for itself shows up too.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::cfg::{self, Cfg, Observed};
+use intcode::VM;
+
+const USAGE: &str = "usage: intcode-cfg [--run [-i <inputs>] [-a <text>] [--max-steps N]] [-o <graph.dot>] <program.txt>";
+
+// Keeps a program that never halts from running forever
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut run = false;
+    let mut inputs = Vec::new();
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    let mut out = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--run" => run = true,
+            "-i" => {
+                for v in value().split(',') {
+                    inputs.push(v.trim().parse().unwrap_or_else(|_| usage()));
+                }
+            }
+            "-a" => inputs.extend(value().bytes().map(i64::from).chain(Some(10))),
+            "--max-steps" => max_steps = value().parse().unwrap_or_else(|_| usage()),
+            "-o" => out = Some(value()),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let program = intcode::read_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+
+    let (code, observed) = if run {
+        let mut vm = VM::new(program.clone());
+        for v in inputs {
+            vm.push_input(v);
+        }
+        let (observed, stop) = cfg::observe(&mut vm, max_steps);
+        match stop {
+            Ok(reason) => eprintln!("stopped: {:?}", reason),
+            Err(e) => eprintln!("error: {}", e),
+        }
+        (vm.memory().to_vec(program.len()), observed)
+    } else {
+        (program, Observed::default())
+    };
+    let graph = Cfg::build(&code, &observed);
+    eprintln!(
+        "{} blocks, {} edges, {} unresolved",
+        graph.blocks.len(),
+        graph.edges.len(),
+        graph.unresolved.len()
+    );
+    match out {
+        Some(out) => fs::write(&out, graph.to_dot()).unwrap_or_else(|e| {
+            eprintln!("{}: {}", out, e);
+            process::exit(1);
+        }),
+        None => print!("{}", graph.to_dot()),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
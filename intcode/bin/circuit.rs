@@ -0,0 +1,33 @@
+//! Runs an amplifier circuit and prints every value that crossed each edge.
+//!
+//! Usage: intcode-circuit <circuit.txt> <program.txt>
+//!
+//! See `intcode::circuit` for the circuit description format.
+
+use std::env;
+use std::process;
+
+use intcode::circuit::Circuit;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: intcode-circuit <circuit.txt> <program.txt>");
+        process::exit(2);
+    }
+    let circuit = Circuit::load(&args[0]).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[0], e);
+        process::exit(1);
+    });
+    let program = intcode::read_program(&args[1]).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    });
+    match circuit.run(&program) {
+        Ok(run) => print!("{}", run),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
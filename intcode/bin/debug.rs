@@ -0,0 +1,41 @@
+//! Interactive debugger for Intcode programs, e.g. a day's puzzle input.
+//!
+//! Usage: intcode-debug <program.txt>
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use intcode::debug::Debugger;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match args.as_slice() {
+        [path] => path,
+        _ => {
+            eprintln!("usage: intcode-debug <program.txt>");
+            process::exit(2);
+        }
+    };
+    let program = intcode::read_program(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+
+    println!("loaded {} words from {}, `help` lists commands", program.len(), path);
+    let mut debugger = Debugger::new(program);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        match debugger.execute(&line) {
+            Some(text) => print!("{}", text),
+            None => break,
+        }
+    }
+}
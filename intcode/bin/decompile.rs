@@ -0,0 +1,79 @@
+//! Decompiles an Intcode program into C-like pseudocode.
+//!
+//! Usage: intcode-decompile [--run [-i <inputs>] [-a <text>] [--max-steps N]]
+//!                          [-o <out.txt>] <program.txt>
+//!
+//! Every function called with the usual return address convention gets its
+//! own body; see the `decompile` module for how locals are named. With
+//! `--run` the program is run first, queueing input as for intcode-cfg, and
+//! what it ran is decompiled from memory as the run left it, including code
+//! it wrote for itself.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::{cfg, decompile, VM};
+
+const USAGE: &str = "usage: intcode-decompile [--run [-i <inputs>] [-a <text>] [--max-steps N]] [-o <out.txt>] <program.txt>";
+
+// Keeps a program that never halts from running forever
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut run = false;
+    let mut inputs = Vec::new();
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    let mut out = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--run" => run = true,
+            "-i" => {
+                for v in value().split(',') {
+                    inputs.push(v.trim().parse().unwrap_or_else(|_| usage()));
+                }
+            }
+            "-a" => inputs.extend(value().bytes().map(i64::from).chain(Some(10))),
+            "--max-steps" => max_steps = value().parse().unwrap_or_else(|_| usage()),
+            "-o" => out = Some(value()),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let program = intcode::read_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+
+    let functions = if run {
+        let mut vm = VM::new(program.clone());
+        for v in inputs {
+            vm.push_input(v);
+        }
+        let (observed, stop) = cfg::observe(&mut vm, max_steps);
+        match stop {
+            Ok(reason) => eprintln!("stopped: {:?}", reason),
+            Err(e) => eprintln!("error: {}", e),
+        }
+        decompile::decompile_observed(&vm.memory().to_vec(program.len()), &observed)
+    } else {
+        decompile::decompile(&program)
+    };
+    let text = decompile::to_text(&functions);
+    match out {
+        Some(out) => fs::write(&out, text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", out, e);
+            process::exit(1);
+        }),
+        None => print!("{}", text),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,77 @@
+//! Prints a disassembly listing of an Intcode program.
+//!
+//! Usage: intcode-disasm [--json] [--run [-i <inputs>] [-a <text>] [--max-steps N]]
+//!                       <program.txt>
+//!
+//! With `--run` the program is run first, with `-i` and `-a` queueing input
+//! as for intcode-profile, and the listing follows what actually executed.
+//! Which words were code and which data, and every write to code that had
+//! already run, are reported on stderr.
+
+use std::env;
+use std::process;
+
+use intcode::codemap::{CodeMap, OnModify};
+use intcode::{disasm, Budget, VM};
+
+const USAGE: &str = "usage: intcode-disasm [--json] [--run [-i <inputs>] [-a <text>] [--max-steps N]] <program.txt>";
+
+// Keeps a program that never halts from running forever
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut json = false;
+    let mut run = false;
+    let mut inputs = Vec::new();
+    let mut budget = Budget::steps(DEFAULT_MAX_STEPS);
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--json" => json = true,
+            "--run" => run = true,
+            "-i" => {
+                for v in value().split(',') {
+                    inputs.push(v.trim().parse().unwrap_or_else(|_| usage()));
+                }
+            }
+            "-a" => inputs.extend(value().bytes().map(i64::from).chain(Some(10))),
+            "--max-steps" => budget = Budget::steps(value().parse().unwrap_or_else(|_| usage())),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let program = intcode::read_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+
+    let lines = if run {
+        let mut vm = VM::new(program.clone());
+        vm.attach_code_map(CodeMap::new(OnModify::Record));
+        for v in inputs {
+            vm.push_input(v);
+        }
+        match vm.run_within(budget) {
+            Ok(reason) => eprintln!("stopped: {:?}", reason),
+            Err(e) => eprintln!("error: {}", e),
+        }
+        let map = vm.detach_code_map().unwrap();
+        eprint!("{}", map.report());
+        disasm::disassemble_with(&program, &map)
+    } else {
+        disasm::disassemble(&program)
+    };
+    if json {
+        print!("{}", disasm::to_json(&lines));
+    } else {
+        print!("{}", disasm::to_text(&lines));
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,46 @@
+//! Runs random programs on every way the VM can execute them and reports
+//! the first one they disagree on, minimized.
+//!
+//! Usage: intcode-fuzz [--seed N] [--runs N] [--max-steps N]
+//!
+//! Exits with status 1 if a divergence was found.
+
+use std::env;
+use std::process;
+
+use intcode::fuzz;
+
+const USAGE: &str = "usage: intcode-fuzz [--seed N] [--runs N] [--max-steps N]";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut seed = 0;
+    let mut runs = 10_000;
+    let mut max_steps = 1_000;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| usage())
+        };
+        match arg.as_str() {
+            "--seed" => seed = value(),
+            "--runs" => runs = value(),
+            "--max-steps" => max_steps = value(),
+            _ => usage(),
+        }
+    }
+
+    match fuzz::fuzz(seed, runs, max_steps) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!("{} programs, no divergence (seed {})", runs, seed),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,74 @@
+//! Runs an Intcode program and reports where its instructions went.
+//!
+//! Usage: intcode-profile [-i <inputs>] [-a <text>] [--top N] [--max-steps N]
+//!                        [--csv <file>] <program.txt>
+//!
+//! `-i` queues comma separated numbers and `-a` a line of ASCII text; both
+//! can be repeated and are queued in order. The program runs until it halts,
+//! runs out of input or hits the step limit.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::{Budget, Profiler, StopReason, VM};
+
+const USAGE: &str = "usage: intcode-profile [-i <inputs>] [-a <text>] [--top N] [--max-steps N] [--csv <file>] <program.txt>";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut inputs = Vec::new();
+    let mut top = 20;
+    let mut budget = Budget::unlimited();
+    let mut csv = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-i" => {
+                for v in value().split(',') {
+                    inputs.push(v.trim().parse().unwrap_or_else(|_| usage()));
+                }
+            }
+            "-a" => inputs.extend(value().bytes().map(i64::from).chain(Some(10))),
+            "--top" => top = value().parse().unwrap_or_else(|_| usage()),
+            "--max-steps" => budget = budget.with_steps(value().parse().unwrap_or_else(|_| usage())),
+            "--csv" => csv = Some(value()),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let program = intcode::read_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+
+    let mut vm = VM::new(program);
+    vm.attach_profiler(Profiler::new());
+    for v in inputs {
+        vm.push_input(v);
+    }
+    let stop = vm.run_within(budget);
+    match stop {
+        Ok(StopReason::Halted) => eprintln!("halted"),
+        Ok(StopReason::NeedsInput) => eprintln!("stopped waiting for input"),
+        Ok(StopReason::BudgetExhausted) => eprintln!("stopped after the step limit"),
+        Ok(reason) => eprintln!("stopped: {:?}", reason),
+        Err(e) => eprintln!("error: {}", e),
+    }
+
+    let profiler = vm.detach_profiler().unwrap();
+    print!("{}", profiler.report(vm.memory(), top));
+    if let Some(csv) = csv {
+        fs::write(&csv, profiler.to_csv(vm.memory())).unwrap_or_else(|e| {
+            eprintln!("{}: {}", csv, e);
+            process::exit(1);
+        });
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,204 @@
+//! Instructions decoded once and reused until the program overwrites them.
+
+use std::mem;
+use std::sync::Arc;
+
+use crate::instruction::{Mode, Opcode};
+use crate::memory::Memory;
+
+// Addresses past this aren't cached, so a jump far out doesn't allocate a
+// huge table
+const MAX_CACHED_PC: usize = 1 << 20;
+
+/// An instruction with its opcode, modes and raw operands pulled out of
+/// memory.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decoded {
+    pub opcode: Opcode,
+    /// Words the instruction occupies. Zero marks an empty cache slot.
+    pub len: u8,
+    pub modes: [Mode; 3],
+    pub args: [i64; 3],
+}
+
+const EMPTY: Decoded = Decoded {
+    opcode: Opcode::Halt,
+    len: 0,
+    modes: [Mode::Position; 3],
+    args: [0; 3],
+};
+
+impl Decoded {
+    /// Decodes the instruction at `pc` the way the interpreter would execute
+    /// it. Returns `None` for anything that would fault, so the interpreter
+    /// can run it and report the error.
+    pub fn decode(memory: &Memory, pc: usize) -> Option<Decoded> {
+        Decoded::decode_with(|addr| memory.get(addr), memory.limit(), pc)
+    }
+
+    // Decodes the instruction at `pc`, reading words with `word`
+    fn decode_with<F>(word: F, limit: Option<usize>, pc: usize) -> Option<Decoded>
+    where
+        F: Fn(usize) -> i64,
+    {
+        let code = word(pc);
+        if code < 0 {
+            return None;
+        }
+        let opcode = Opcode::from_code(code % 100)?;
+        let n = opcode.num_params();
+        if limit.is_some_and(|l| pc + n >= l) {
+            return None;
+        }
+        let mut decoded = Decoded {
+            opcode,
+            len: 1 + n as u8,
+            ..EMPTY
+        };
+        // Constant divisors, which are much cheaper than looking them up
+        let digits = [code / 100 % 10, code / 1_000 % 10, code / 10_000 % 10];
+        for (i, &digit) in digits.iter().enumerate().take(n) {
+            let mode = Mode::from_digit(digit)?;
+            if mode == Mode::Immediate && opcode.writes() && i == n - 1 {
+                return None;
+            }
+            decoded.modes[i] = mode;
+            decoded.args[i] = word(pc + 1 + i);
+        }
+        Some(decoded)
+    }
+}
+
+// Shared between forked VMs like their memory pages, and copied by whichever
+// side changes it first. From then on it's owned, so later changes don't
+// have to check for other owners.
+#[derive(Debug)]
+enum Entries {
+    Owned(Vec<Decoded>),
+    Shared(Arc<Vec<Decoded>>),
+}
+
+impl Entries {
+    fn get(&self) -> &[Decoded] {
+        match self {
+            Entries::Owned(entries) => entries,
+            Entries::Shared(entries) => entries,
+        }
+    }
+
+    fn share(&mut self) {
+        if let Entries::Owned(entries) = self {
+            *self = Entries::Shared(Arc::new(mem::take(entries)));
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut Vec<Decoded> {
+        if let Entries::Shared(entries) = self {
+            *self = Entries::Owned(entries.to_vec());
+        }
+        match self {
+            Entries::Owned(entries) => entries,
+            Entries::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+impl Clone for Entries {
+    fn clone(&self) -> Entries {
+        match self {
+            Entries::Owned(entries) => Entries::Owned(entries.clone()),
+            Entries::Shared(entries) => Entries::Shared(entries.clone()),
+        }
+    }
+}
+
+impl Default for Entries {
+    fn default() -> Entries {
+        Entries::Owned(Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: Entries,
+}
+
+impl DecodeCache {
+    /// A cache with `program` decoded ahead of time, one instruction after
+    /// the other from its start. Forks share it, so a VM that's forked for
+    /// every run doesn't leave each fork to decode the program again.
+    pub fn prime(program: &[i64]) -> DecodeCache {
+        let word = |addr| program.get(addr).copied().unwrap_or(0);
+        let len = program.len().min(MAX_CACHED_PC);
+        let mut entries = Vec::with_capacity(len);
+        while entries.len() < len {
+            match Decoded::decode_with(word, None, entries.len()) {
+                Some(decoded) => {
+                    entries.push(decoded);
+                    entries.extend((1..decoded.len).map(|_| EMPTY));
+                }
+                None => entries.push(EMPTY),
+            }
+        }
+        entries.truncate(len);
+        DecodeCache {
+            entries: Entries::Shared(Arc::new(entries)),
+        }
+    }
+
+    pub fn get(&self, pc: usize) -> Option<&Decoded> {
+        self.entries.get().get(pc).filter(|d| d.len != 0)
+    }
+
+    pub fn insert(&mut self, pc: usize, decoded: Decoded) {
+        if pc >= MAX_CACHED_PC {
+            return;
+        }
+        let entries = self.entries.get_mut();
+        if pc >= entries.len() {
+            entries.resize(pc + 1, EMPTY);
+        }
+        entries[pc] = decoded;
+    }
+
+    /// Updates the instructions that cover `addr` for `val` being written
+    /// there. Operands are patched in place, since self-modifying programs
+    /// mostly rewrite those, and instructions whose code is overwritten are
+    /// forgotten.
+    pub fn write(&mut self, addr: usize, val: i64) {
+        let start = addr.saturating_sub(3);
+        let end = (addr + 1).min(self.entries.get().len());
+        if start >= end {
+            return;
+        }
+        // Most writes are to data, so only look before copying a shared table
+        let covers = |(pc, entry): (usize, &Decoded)| pc + entry.len as usize > addr;
+        if !(start..end).zip(&self.entries.get()[start..end]).any(covers) {
+            return;
+        }
+        let entries = self.entries.get_mut();
+        for (pc, entry) in (start..end).zip(&mut entries[start..end]) {
+            if pc == addr {
+                entry.len = 0;
+            } else if pc + entry.len as usize > addr {
+                entry.args[addr - pc - 1] = val;
+            }
+        }
+    }
+
+    /// A copy that shares the table with this cache until either side
+    /// changes it.
+    pub fn fork(&mut self) -> DecodeCache {
+        self.entries.share();
+        self.clone()
+    }
+
+    #[cfg(test)]
+    pub fn is_shared(&self) -> bool {
+        matches!(&self.entries, Entries::Shared(entries) if Arc::strong_count(entries) > 1)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = Entries::default();
+    }
+}
@@ -0,0 +1,282 @@
+//! Control flow graphs of Intcode programs.
+//!
+//! `Cfg::build` splits the code the disassembler finds into basic blocks,
+//! starting a new block at every jump target and after every jump or halt.
+//! Jumps whose target is an immediate are resolved statically. Those that
+//! jump through memory or the relative base can't be, so `observe` runs the
+//! program and records the jumps it actually takes and every address it
+//! executed; passing them to `build` fills in those edges and adds any code
+//! that only they lead to. A program that rewrites its own code should be
+//! built from memory as the run left it, so the rewritten words decode.
+//!
+//! Blocks that jump somewhere unknown, or that run on into words that don't
+//! decode, are listed as unresolved rather than left out of the graph.
+//!
+//! `Cfg::to_dot` writes the graph as Graphviz DOT, with each block's
+//! disassembly inside its node:
+//!
+//! ```text
+//! dot -Tsvg cfg.dot -o cfg.svg
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disasm::{self, Line};
+use crate::error::VmError;
+use crate::instruction::{mode_digit, Instruction, Opcode};
+use crate::vm::{Budget, StopReason, VM};
+
+/// A straight run of instructions that is only entered at the top and only
+/// left at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// First address after the block.
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+}
+
+impl Block {
+    fn last(&self) -> &Instruction {
+        &self.instructions.last().unwrap().1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Fallthrough,
+    /// A jump to an immediate target.
+    Jump,
+    /// A jump taken while the program was observed, whose target isn't known
+    /// statically.
+    Dynamic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    /// Start of the block the edge leaves.
+    pub from: usize,
+    /// Start of the block it enters.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    /// Ordered by address.
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+    /// Starts of blocks ending in a jump whose target isn't an immediate and
+    /// that was never seen taken, or that run on into words that don't decode
+    /// as code, so where they go is unknown.
+    pub unresolved: Vec<usize>,
+}
+
+/// What `observe` saw a program do.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Observed {
+    /// Every jump taken, as (address of the jump, address jumped to).
+    pub jumps: BTreeSet<(usize, usize)>,
+    /// Every address an instruction was executed from.
+    pub executed: BTreeSet<usize>,
+}
+
+/// Runs `vm` for at most `max_steps` instructions and returns what it did,
+/// along with why it stopped. Outputs are collected in the VM as usual.
+pub fn observe(vm: &mut VM, max_steps: u64) -> (Observed, Result<StopReason, VmError>) {
+    let mut observed = Observed::default();
+    for _ in 0..max_steps {
+        let pc = vm.pc();
+        let taken = jump_taken(vm);
+        // Unlike `resume_within`, this keeps outputs in the VM
+        match vm.run_within(Budget::steps(1)) {
+            Ok(StopReason::BudgetExhausted) => {
+                observed.executed.insert(pc);
+                if taken {
+                    observed.jumps.insert((pc, vm.pc()));
+                }
+            }
+            stop => {
+                // Of the instructions that stop the run, only a halt ran
+                if let Ok(StopReason::Halted) = stop {
+                    observed.executed.insert(pc);
+                }
+                return (observed, stop);
+            }
+        }
+    }
+    (observed, Ok(StopReason::BudgetExhausted))
+}
+
+// Whether the instruction at the VM's pc is a jump whose condition holds
+fn jump_taken(vm: &VM) -> bool {
+    let pc = vm.pc();
+    let code = vm.get_state(pc);
+    let op = match Opcode::from_code(code % 100) {
+        Some(op) if op.is_jump() => op,
+        _ => return false,
+    };
+    let p = vm.get_state(pc + 1);
+    let condition = match mode_digit(code, 0) {
+        0 if p >= 0 => vm.get_state(p as usize),
+        1 => p,
+        2 if p.wrapping_add(vm.relative_base()) >= 0 => {
+            vm.get_state(p.wrapping_add(vm.relative_base()) as usize)
+        }
+        // The jump faults instead
+        _ => return false,
+    };
+    (condition != 0) == (op == Opcode::JumpIfTrue)
+}
+
+impl Cfg {
+    /// Builds the graph of `program`, using what was `observed` at runtime
+    /// (see `observe`) for what static analysis can't resolve. Pass
+    /// `Observed::default()` for a purely static graph.
+    pub fn build(program: &[i64], observed: &Observed) -> Cfg {
+        let jumps = &observed.jumps;
+        let mut entry_points = vec![0];
+        entry_points.extend(jumps.iter().map(|(_, to)| *to).filter(|a| *a < program.len()));
+        entry_points.extend(observed.executed.iter().filter(|a| **a < program.len()));
+        let code: Vec<(usize, Instruction)> = disasm::disassemble_from(program, &entry_points)
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Code { addr, instruction } => Some((addr, instruction)),
+                _ => None,
+            })
+            .collect();
+
+        let mut leaders: BTreeSet<usize> = entry_points.iter().copied().collect();
+        for (addr, instruction) in &code {
+            if let Some(target) = instruction.jump_target() {
+                leaders.insert(target as usize);
+            }
+            if instruction.opcode.is_jump() || instruction.opcode == Opcode::Halt {
+                leaders.insert(addr + instruction.size());
+            }
+        }
+
+        let mut blocks: Vec<Block> = Vec::new();
+        for (addr, instruction) in code {
+            let size = instruction.size();
+            match blocks.last_mut() {
+                Some(block) if block.end == addr && !leaders.contains(&addr) => {
+                    block.end += size;
+                    block.instructions.push((addr, instruction));
+                }
+                _ => blocks.push(Block {
+                    start: addr,
+                    end: addr + size,
+                    instructions: vec![(addr, instruction)],
+                }),
+            }
+        }
+
+        let starts: BTreeSet<usize> = blocks.iter().map(|b| b.start).collect();
+        let mut edges = BTreeSet::new();
+        let mut unresolved = Vec::new();
+        for block in &blocks {
+            let last = block.last();
+            if !last.ends_block() {
+                if starts.contains(&block.end) {
+                    edges.insert(Edge {
+                        from: block.start,
+                        to: block.end,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                } else {
+                    unresolved.push(block.start);
+                }
+            }
+            match last.jump_target() {
+                Some(target) if starts.contains(&(target as usize)) => {
+                    edges.insert(Edge {
+                        from: block.start,
+                        to: target as usize,
+                        kind: EdgeKind::Jump,
+                    });
+                }
+                Some(_) => {}
+                None if last.opcode.is_jump() => {
+                    let pc = block.instructions.last().unwrap().0;
+                    let mut seen = false;
+                    for (_, to) in jumps.range((pc, 0)..=(pc, usize::MAX)) {
+                        seen = true;
+                        if starts.contains(to) {
+                            edges.insert(Edge {
+                                from: block.start,
+                                to: *to,
+                                kind: EdgeKind::Dynamic,
+                            });
+                        }
+                    }
+                    if !seen && unresolved.last() != Some(&block.start) {
+                        unresolved.push(block.start);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        Cfg {
+            blocks,
+            edges: edges.into_iter().collect(),
+            unresolved,
+        }
+    }
+
+    /// The block starting at `start`.
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks
+            .binary_search_by_key(&start, |b| b.start)
+            .ok()
+            .map(|i| &self.blocks[i])
+    }
+
+    /// Edges out of each block, keyed by block start.
+    pub fn successors(&self) -> BTreeMap<usize, Vec<Edge>> {
+        let mut out: BTreeMap<usize, Vec<Edge>> = BTreeMap::new();
+        for edge in &self.edges {
+            out.entry(edge.from).or_default().push(*edge);
+        }
+        out
+    }
+
+    /// The graph in Graphviz DOT. Jumps are drawn in blue, dynamic edges
+    /// dashed, and blocks whose jump couldn't be resolved point at a `?`
+    /// node.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph intcode {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in &self.blocks {
+            let mut label = String::new();
+            for (addr, instruction) in &block.instructions {
+                write!(label, "{:04}: {}\\l", addr, escape(&instruction.to_string())).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [color=blue]",
+                EdgeKind::Dynamic => " [color=blue, style=dashed]",
+            };
+            writeln!(out, "    b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+        }
+        if !self.unresolved.is_empty() {
+            out.push_str("    unresolved [label=\"?\", shape=circle];\n");
+            for start in &self.unresolved {
+                writeln!(out, "    b{} -> unresolved [style=dotted];", start).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,346 @@
+//! Amplifier circuits of any shape, described in a small text format.
+//!
+//! ```text
+//! ; two feedback loops sharing amplifier C, with C's output also tapped
+//! amp A 5
+//! amp B 6
+//! amp C 7
+//! tap thrust
+//! input A 0
+//! A -> C
+//! B -> C
+//! C -> A, B, thrust
+//! ```
+//!
+//! `amp NAME [PHASE]` declares an amplifier, which is sent its phase setting
+//! before anything else. `tap NAME` declares a named sink that only records
+//! what reaches it. `input NAME V,...` queues values for an amplifier after its
+//! phase. `FROM -> TO, ...` connects an amplifier's output to amplifiers or
+//! taps: every value it outputs is copied to each of them (fan out), and an
+//! amplifier fed by several edges reads their values in the order they
+//! arrive (fan in). `;` starts a comment.
+//!
+//! Every amplifier runs the same program. `Circuit::run` keeps going until
+//! each amplifier has halted or is waiting for input nobody will send, and
+//! reports every value that crossed every edge.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::VmError;
+use crate::exec::{channel, Executor, Sender};
+use crate::vm::{StopReason, VM};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Circuit {
+    amps: Vec<Amp>,
+    taps: Vec<String>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Amp {
+    name: String,
+    phase: Option<i64>,
+    inputs: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Edge {
+    // Index into `amps`
+    from: usize,
+    to: Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Amp(usize),
+    Tap(usize),
+}
+
+#[derive(Debug)]
+pub enum CircuitError {
+    Io(io::Error),
+    /// One based line of the description that couldn't be parsed.
+    Invalid { line: usize, message: String },
+    /// An amplifier faulted.
+    Vm { amplifier: String, error: VmError },
+}
+
+impl fmt::Display for CircuitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CircuitError::Io(e) => write!(f, "{}", e),
+            CircuitError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+            CircuitError::Vm { amplifier, error } => write!(f, "amplifier {}: {}", amplifier, error),
+        }
+    }
+}
+
+impl Error for CircuitError {}
+
+impl From<io::Error> for CircuitError {
+    fn from(e: io::Error) -> CircuitError {
+        CircuitError::Io(e)
+    }
+}
+
+/// What happened when a circuit ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    /// Every edge as its endpoints' names and the values that crossed it, in
+    /// the order the edges were declared.
+    pub edges: Vec<(String, String, Vec<i64>)>,
+    /// Amplifiers left waiting for input instead of halting.
+    pub waiting: Vec<String>,
+}
+
+impl Run {
+    /// Values that reached the tap or amplifier `name`, from every edge into
+    /// it, in the order the edges were declared.
+    pub fn received(&self, name: &str) -> Vec<i64> {
+        self.edges
+            .iter()
+            .filter(|(_, to, _)| to == name)
+            .flat_map(|(_, _, values)| values.iter().copied())
+            .collect()
+    }
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (from, to, values) in &self.edges {
+            let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+            writeln!(f, "{} -> {}: {}", from, to, values.join(","))?;
+        }
+        if !self.waiting.is_empty() {
+            writeln!(f, "waiting for input: {}", self.waiting.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Circuit {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Circuit, CircuitError> {
+        Circuit::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Circuit, CircuitError> {
+        let mut circuit = Circuit {
+            amps: Vec::new(),
+            taps: Vec::new(),
+            edges: Vec::new(),
+        };
+        // Edges and inputs can name amplifiers and taps declared further down,
+        // so they're resolved once every declaration has been read
+        let mut edges = Vec::new();
+        let mut inputs = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |message: String| CircuitError::Invalid {
+                line: idx + 1,
+                message,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let Some(arrow) = line.find("->") {
+                let from = line[..arrow].trim();
+                let targets: Vec<&str> = line[arrow + 2..].split(',').map(str::trim).collect();
+                if from.is_empty() || targets.iter().any(|t| t.is_empty()) {
+                    return Err(invalid("expected `FROM -> TO, ...`".to_string()));
+                }
+                edges.push((idx + 1, from, targets));
+                continue;
+            }
+            match words[0] {
+                "amp" | "tap" if words.len() < 2 => {
+                    return Err(invalid(format!("`{}` needs a name", words[0])))
+                }
+                "amp" | "tap" if circuit.node(words[1]).is_some() => {
+                    return Err(invalid(format!("`{}` is declared twice", words[1])))
+                }
+                "amp" if words.len() <= 3 => {
+                    let phase = match words.get(2) {
+                        Some(p) => Some(parse(p).map_err(invalid)?),
+                        None => None,
+                    };
+                    circuit.amps.push(Amp {
+                        name: words[1].to_string(),
+                        phase,
+                        inputs: Vec::new(),
+                    });
+                }
+                "tap" if words.len() == 2 => circuit.taps.push(words[1].to_string()),
+                "input" if words.len() >= 3 => {
+                    let values = words[2..]
+                        .join(" ")
+                        .split(',')
+                        .map(|v| match v.trim() {
+                            v if v.contains(char::is_whitespace) => {
+                                Err(format!("missing comma in `{}`", v))
+                            }
+                            v => parse(v),
+                        })
+                        .collect::<Result<Vec<i64>, String>>()
+                        .map_err(invalid)?;
+                    inputs.push((idx + 1, words[1], values));
+                }
+                "amp" | "tap" | "input" => {
+                    return Err(invalid(format!("wrong number of words for `{}`", words[0])))
+                }
+                other => return Err(invalid(format!("unknown directive `{}`", other))),
+            }
+        }
+
+        for (line, name, values) in inputs {
+            match circuit.node(name) {
+                Some(Node::Amp(amp)) => circuit.amps[amp].inputs.extend(values),
+                Some(Node::Tap(_)) => return Err(not_an_amp(line, name)),
+                None => return Err(unknown(line, name)),
+            }
+        }
+        for (line, from, targets) in edges {
+            let from = match circuit.node(from) {
+                Some(Node::Amp(amp)) => amp,
+                Some(Node::Tap(_)) => return Err(not_an_amp(line, from)),
+                None => return Err(unknown(line, from)),
+            };
+            for target in targets {
+                let to = circuit.node(target).ok_or_else(|| unknown(line, target))?;
+                circuit.edges.push(Edge { from, to });
+            }
+        }
+        Ok(circuit)
+    }
+
+    fn node(&self, name: &str) -> Option<Node> {
+        if let Some(amp) = self.amps.iter().position(|a| a.name == name) {
+            Some(Node::Amp(amp))
+        } else {
+            self.taps.iter().position(|t| t == name).map(Node::Tap)
+        }
+    }
+
+    fn name(&self, node: Node) -> &str {
+        match node {
+            Node::Amp(amp) => &self.amps[amp].name,
+            Node::Tap(tap) => &self.taps[tap],
+        }
+    }
+
+    /// Runs every amplifier on its own copy of `program` until none of them
+    /// can do anything more.
+    pub fn run(&self, program: &[i64]) -> Result<Run, CircuitError> {
+        let mut template = VM::new(program.to_vec());
+        let (senders, receivers): (Vec<_>, Vec<_>) = self
+            .amps
+            .iter()
+            .map(|amp| {
+                let (tx, rx) = channel();
+                for v in amp.phase.iter().chain(&amp.inputs) {
+                    tx.send(*v);
+                }
+                (tx, rx)
+            })
+            .unzip();
+        let history = RefCell::new(vec![Vec::new(); self.edges.len()]);
+
+        let mut vms: Vec<VM> = self.amps.iter().map(|_| template.fork()).collect();
+        let mut executor = Executor::new();
+        let mut routers = Vec::new();
+        for (amp, (vm, input)) in vms.iter_mut().zip(&receivers).enumerate() {
+            let (to_router, outputs) = channel();
+            executor.spawn(async move {
+                // Owning the sender closes the router once the amplifier stops
+                let to_router: Sender<i64> = to_router;
+                vm.run_async(input, &to_router).await
+            });
+            routers.push((amp, outputs));
+        }
+        // Each amplifier's outputs are copied along all of its edges
+        for (amp, outputs) in routers {
+            let (history, senders, edges) = (&history, &senders, &self.edges);
+            executor.spawn(async move {
+                while let Some(v) = outputs.recv().await {
+                    for (idx, edge) in edges.iter().enumerate() {
+                        if edge.from == amp {
+                            history.borrow_mut()[idx].push(v);
+                            if let Node::Amp(to) = edge.to {
+                                senders[to].send(v);
+                            }
+                        }
+                    }
+                }
+                Ok(StopReason::Halted)
+            });
+        }
+
+        let mut waiting = Vec::new();
+        for (amp, result) in executor.run().into_iter().enumerate().take(self.amps.len()) {
+            let name = &self.amps[amp].name;
+            match result {
+                Some(Ok(StopReason::Halted)) => {}
+                Some(Ok(_)) | None => waiting.push(name.clone()),
+                Some(Err(error)) => {
+                    return Err(CircuitError::Vm {
+                        amplifier: name.clone(),
+                        error,
+                    })
+                }
+            }
+        }
+        let edges = self
+            .edges
+            .iter()
+            .zip(history.into_inner())
+            .map(|(edge, values)| {
+                let from = self.amps[edge.from].name.clone();
+                (from, self.name(edge.to).to_string(), values)
+            })
+            .collect();
+        Ok(Run { edges, waiting })
+    }
+}
+
+fn parse(s: &str) -> Result<i64, String> {
+    s.parse().map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn unknown(line: usize, name: &str) -> CircuitError {
+    CircuitError::Invalid {
+        line,
+        message: format!("no amplifier or tap named `{}`", name),
+    }
+}
+
+fn not_an_amp(line: usize, name: &str) -> CircuitError {
+    CircuitError::Invalid {
+        line,
+        message: format!("`{}` is a tap, not an amplifier", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_values_need_commas_between_them() {
+        let circuit = Circuit::parse("amp A\ninput A 4, 5 ,6").unwrap();
+        assert_eq!(circuit.amps[0].inputs, [4, 5, 6]);
+
+        match Circuit::parse("amp A\ninput A 4 5, 6") {
+            Err(CircuitError::Invalid { line, message }) => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "missing comma in `4 5`");
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Tells code from data by watching a program run.
+//!
+//! Attach a `CodeMap` with `VM::attach_code_map` and every word of every
+//! instruction the VM executes is marked as code, and every word an
+//! instruction reads or writes through an operand as data. A write to a word
+//! that has already been executed is self-modifying code: with
+//! `OnModify::Record` it's kept as a `Modification`, with `OnModify::Trap`
+//! the writing instruction faults with `ErrorKind::SelfModifyingCode` before
+//! it does anything.
+//!
+//! `disasm::disassemble_with` takes the finished map, so a listing shows
+//! what actually ran rather than what static analysis could find.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::instruction::{Instruction, Opcode};
+
+/// What to do when a program writes over code it has executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnModify {
+    /// Let the write happen and record it.
+    Record,
+    /// Fault at the writing instruction instead.
+    Trap,
+}
+
+/// What a word was used for, if anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Only ever executed.
+    Code,
+    /// Only ever read or written through an operand.
+    Data,
+    /// Both executed and used as data.
+    Mixed,
+}
+
+// Bits of `Word::flags`
+const OPCODE: u8 = 1;
+const OPERAND: u8 = 2;
+const READ: u8 = 4;
+const WRITTEN: u8 = 8;
+const MODIFIED: u8 = 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Word {
+    flags: u8,
+    // Start of the last executed instruction covering the word
+    instruction: usize,
+}
+
+/// A write over code that had already been executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Modification {
+    /// Address of the writing instruction.
+    pub pc: usize,
+    /// Address written to.
+    pub addr: usize,
+    pub old: i64,
+    pub new: i64,
+    /// Start of the executed instruction the word belongs to.
+    pub instruction: usize,
+    /// That instruction's words as they were before the write.
+    pub words: Vec<i64>,
+}
+
+/// Formats as e.g. `pc 12 wrote 7 to 21 (was 3) in ADD [9], [10] -> [11] at 20`.
+impl fmt::Display for Modification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pc {} wrote {} to {} (was {}) in ",
+            self.pc, self.new, self.addr, self.old
+        )?;
+        match Instruction::decode(&self.words, 0) {
+            Ok(instruction) => write!(f, "{}", instruction)?,
+            Err(_) => write!(f, "{:?}", self.words)?,
+        }
+        write!(f, " at {}", self.instruction)
+    }
+}
+
+// Modifications kept in full; any more are only counted
+const MAX_MODIFICATIONS: usize = 1 << 12;
+
+#[derive(Debug, Clone)]
+pub struct CodeMap {
+    on_modify: OnModify,
+    // Keyed by address, since programs can touch words anywhere
+    words: HashMap<usize, Word>,
+    modifications: Vec<Modification>,
+    modification_count: u64,
+}
+
+impl CodeMap {
+    pub fn new(on_modify: OnModify) -> CodeMap {
+        CodeMap {
+            on_modify,
+            words: HashMap::new(),
+            modifications: Vec::new(),
+            modification_count: 0,
+        }
+    }
+
+    pub fn on_modify(&self) -> OnModify {
+        self.on_modify
+    }
+
+    fn word_mut(&mut self, addr: usize) -> &mut Word {
+        self.words.entry(addr).or_default()
+    }
+
+    fn flags(&self, addr: usize) -> u8 {
+        self.words.get(&addr).map_or(0, |w| w.flags)
+    }
+
+    pub(crate) fn executed(&mut self, pc: usize, len: usize) {
+        for addr in pc..pc + len {
+            let word = self.word_mut(addr);
+            word.flags |= if addr == pc { OPCODE } else { OPERAND };
+            word.instruction = pc;
+        }
+    }
+
+    pub(crate) fn read(&mut self, addr: usize) {
+        self.word_mut(addr).flags |= READ;
+    }
+
+    /// The start of the executed instruction a write to `addr` would
+    /// overwrite, if the write should trap.
+    pub(crate) fn trap(&self, addr: usize) -> Option<usize> {
+        if self.on_modify == OnModify::Trap && self.is_code(addr) {
+            Some(self.words[&addr].instruction)
+        } else {
+            None
+        }
+    }
+
+    // `memory` reads the words of the overwritten instruction, before the
+    // write
+    pub(crate) fn written<F: Fn(usize) -> i64>(
+        &mut self,
+        pc: usize,
+        addr: usize,
+        new: i64,
+        memory: F,
+    ) {
+        let word = self.word_mut(addr);
+        word.flags |= WRITTEN;
+        if word.flags & (OPCODE | OPERAND) == 0 {
+            return;
+        }
+        word.flags |= MODIFIED;
+        let instruction = word.instruction;
+        self.modification_count += 1;
+        if self.modifications.len() < MAX_MODIFICATIONS {
+            // It ran, so its opcode was valid at the time
+            let len = Opcode::from_code(memory(instruction) % 100).map_or(1, |op| op.num_params() + 1);
+            self.modifications.push(Modification {
+                pc,
+                addr,
+                old: memory(addr),
+                new,
+                instruction,
+                words: (instruction..instruction + len).map(&memory).collect(),
+            });
+        }
+    }
+
+    /// Whether the word at `addr` has been executed, as an opcode or an
+    /// operand.
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.flags(addr) & (OPCODE | OPERAND) != 0
+    }
+
+    /// What the word at `addr` was used for, or `None` if it wasn't touched.
+    pub fn kind(&self, addr: usize) -> Option<Kind> {
+        let flags = self.flags(addr);
+        match (flags & (OPCODE | OPERAND) != 0, flags & (READ | WRITTEN) != 0) {
+            (true, false) => Some(Kind::Code),
+            (false, true) => Some(Kind::Data),
+            (true, true) => Some(Kind::Mixed),
+            (false, false) => None,
+        }
+    }
+
+    /// Whether the word at `addr` was written after being executed.
+    pub fn is_modified(&self, addr: usize) -> bool {
+        self.flags(addr) & MODIFIED != 0
+    }
+
+    /// Addresses executed as the start of an instruction, lowest first.
+    pub fn instructions(&self) -> Vec<usize> {
+        self.addresses(|w| w.flags & OPCODE != 0)
+    }
+
+    /// Addresses only ever used as data, lowest first.
+    pub fn data(&self) -> Vec<usize> {
+        self.addresses(|w| w.flags & (OPCODE | OPERAND) == 0 && w.flags & (READ | WRITTEN) != 0)
+    }
+
+    fn addresses<F: Fn(&Word) -> bool>(&self, f: F) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .words
+            .iter()
+            .filter(|(_, w)| f(w))
+            .map(|(addr, _)| *addr)
+            .collect();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    /// Writes over executed code, in the order they happened. Only the first
+    /// few thousand are kept; `modification_count` counts them all.
+    pub fn modifications(&self) -> &[Modification] {
+        &self.modifications
+    }
+
+    pub fn modification_count(&self) -> u64 {
+        self.modification_count
+    }
+
+    /// Runs of consecutive words of the same kind as `(start, end, kind)`,
+    /// with `end` exclusive. Untouched words are left out.
+    pub fn ranges(&self) -> Vec<(usize, usize, Kind)> {
+        let mut ranges: Vec<(usize, usize, Kind)> = Vec::new();
+        for addr in self.addresses(|_| true) {
+            let kind = match self.kind(addr) {
+                Some(k) => k,
+                None => continue,
+            };
+            match ranges.last_mut() {
+                Some((_, end, k)) if *end == addr && *k == kind => *end += 1,
+                _ => ranges.push((addr, addr + 1, kind)),
+            }
+        }
+        ranges
+    }
+
+    /// Human readable summary: the ranges of code and data, then every
+    /// recorded modification.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (start, end, kind) in self.ranges() {
+            writeln!(out, "{:04}..{:04} {:?}", start, end, kind).unwrap();
+        }
+        if self.modification_count > 0 {
+            writeln!(out, "{} writes to executed code:", self.modification_count).unwrap();
+            for m in &self.modifications {
+                writeln!(out, "  {}", m).unwrap();
+            }
+            let missing = self.modification_count - self.modifications.len() as u64;
+            if missing > 0 {
+                writeln!(out, "  ... and {} more", missing).unwrap();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,527 @@
+//! A line based debugger over the VM.
+//!
+//! `Debugger::execute` runs one command and returns what to print, so the
+//! same commands work from the `intcode-debug` REPL or a script. `help` lists
+//! them. An empty line repeats the previous command.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::disasm::{self, Line};
+use crate::error::VmError;
+use crate::instruction::{Instruction, Opcode};
+use crate::profile::Profiler;
+use crate::snapshot::Snapshot;
+use crate::trace::Tracer;
+use crate::vm::{StopReason, VM};
+
+const HELP: &str = "\
+step [N]            execute N instructions (default 1)
+continue            run until a breakpoint, input is needed or the program halts
+break ADDR|OP       stop before the instruction at ADDR, or before any OP (e.g. `break IN`)
+delete [ADDR|OP]    remove a breakpoint, or all of them
+info                show pc, relative base, breakpoints and queues
+mem ADDR [N]        show N words starting at ADDR
+set ADDR VALUE...   write values starting at ADDR
+rb [VALUE]          show or set the relative base
+input VALUE...      queue numeric inputs
+ascii TEXT          queue TEXT followed by a newline
+output              show and clear outputs collected so far, as text if ASCII
+disasm [ADDR] [N]   disassemble N lines around ADDR (default pc)
+trace FILE [A..B] [OP...]
+                    write a JSON Lines trace, optionally only for pcs A..B or some opcodes
+trace off           stop tracing
+profile on|off      count executed instructions per opcode, mode combination and pc
+profile [N]         show the N hottest entries of each (default 20)
+profile csv FILE    export the counts as CSV
+save FILE           write a snapshot of the machine and collected outputs to FILE
+load FILE           restore a snapshot written by `save`, replacing collected outputs
+reset               reload the program, keeping breakpoints, any trace and profiling
+quit
+";
+
+// Lines shown before the target address by `disasm`
+const DISASM_CONTEXT: usize = 4;
+const WORDS_PER_LINE: usize = 8;
+// Words `disasm` reads on either side of an address past the program
+const DISASM_WINDOW: usize = 64;
+// Most words `mem` prints at once
+const MAX_WORDS: usize = 1 << 16;
+
+pub struct Debugger {
+    program: Vec<i64>,
+    vm: VM,
+    outputs: Vec<i64>,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: Vec<Opcode>,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(program: Vec<i64>) -> Debugger {
+        Debugger {
+            vm: VM::new(program.clone()),
+            program,
+            outputs: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    /// Runs one command line. Returns the text to show, or `None` once the
+    /// user asks to quit.
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        let (command, args) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (&line[..], ""),
+        };
+        let result = match command {
+            "" => Ok(String::new()),
+            "s" | "step" => parse_or(args, 1).map(|n| self.step(n)),
+            "c" | "continue" => Ok(self.continue_()),
+            "b" | "break" => self.add_breakpoint(args),
+            "del" | "delete" => self.delete_breakpoint(args),
+            "i" | "info" => Ok(self.info()),
+            "x" | "mem" => self.mem(args),
+            "set" => self.set(args),
+            "rb" => self.relative_base(args),
+            "in" | "input" => self.input(args),
+            "ascii" => {
+                self.vm.write_string(args);
+                self.vm.push_input(10);
+                Ok(format!("queued {} characters\n", args.len() + 1))
+            }
+            "o" | "output" => Ok(self.output()),
+            "d" | "disasm" => self.disasm(args),
+            "trace" => self.trace(args),
+            "profile" => self.profile(args),
+            "save" => self.save(args),
+            "load" => self.load(args),
+            "reset" => Ok(self.reset()),
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" | "exit" => return None,
+            _ => Err(format!("unknown command `{}`, try `help`", command)),
+        };
+        self.last_command = line.clone();
+        Some(result.unwrap_or_else(|e| format!("error: {}\n", e)))
+    }
+
+    fn step(&mut self, n: u64) -> String {
+        let mut executed = 0;
+        let mut out = String::new();
+        while executed < n {
+            let stop = match self.vm.resume_for(1) {
+                Ok(StopReason::BudgetExhausted) => {
+                    executed += 1;
+                    continue;
+                }
+                Ok(StopReason::Output(v)) => {
+                    self.outputs.push(v);
+                    executed += 1;
+                    continue;
+                }
+                // Stepping off the breakpoint we're sitting on
+                Ok(StopReason::Breakpoint(_)) if executed == 0 => continue,
+                stop => stop,
+            };
+            self.describe_stop(&mut out, stop);
+            break;
+        }
+        self.show_current(&mut out);
+        out
+    }
+
+    fn continue_(&mut self) -> String {
+        let start = self.vm.pc();
+        let mut first = true;
+        let mut produced = 0;
+        let mut out = String::new();
+        loop {
+            match self.vm.resume() {
+                Ok(StopReason::Output(v)) => {
+                    self.outputs.push(v);
+                    produced += 1;
+                }
+                Ok(StopReason::Breakpoint(pc)) if first && pc == start => {}
+                stop => {
+                    if produced > 0 {
+                        writeln!(out, "{} new outputs, `output` to show them", produced).unwrap();
+                    }
+                    self.describe_stop(&mut out, stop);
+                    self.show_current(&mut out);
+                    return out;
+                }
+            }
+            first = false;
+        }
+    }
+
+    fn describe_stop(&self, out: &mut String, stop: Result<StopReason, VmError>) {
+        match stop {
+            Ok(StopReason::Halted) => writeln!(out, "halted"),
+            Ok(StopReason::NeedsInput) => writeln!(out, "waiting for input"),
+            Ok(StopReason::Breakpoint(pc)) => writeln!(out, "breakpoint at {:04}", pc),
+            Ok(_) => Ok(()),
+            Err(e) => writeln!(out, "error: {}", e),
+        }
+        .unwrap();
+    }
+
+    fn show_current(&self, out: &mut String) {
+        if !self.vm.halted() {
+            writeln!(out, "{}", self.current()).unwrap();
+        }
+    }
+
+    // The instruction at pc, marked like in `disasm`
+    fn current(&self) -> String {
+        let pc = self.vm.pc();
+        let words: Vec<i64> = (pc..pc + 4).map(|a| self.vm.get_state(a)).collect();
+        match Instruction::decode(&words, 0) {
+            Ok(instruction) => format!("=> {:04}: {}", pc, instruction),
+            Err(e) => format!("=> {:04}: DATA {} ; {}", pc, words[0], e),
+        }
+    }
+
+    fn add_breakpoint(&mut self, args: &str) -> Result<String, String> {
+        match parse_location(args)? {
+            Location::Addr(addr) => {
+                self.breakpoints.insert(addr);
+                self.vm.add_breakpoint(addr);
+                Ok(format!("breakpoint at {:04}\n", addr))
+            }
+            Location::Opcode(op) => {
+                if !self.opcode_breakpoints.contains(&op) {
+                    self.opcode_breakpoints.push(op);
+                }
+                self.vm.add_opcode_breakpoint(op);
+                Ok(format!("breakpoint on {}\n", op.mnemonic()))
+            }
+        }
+    }
+
+    fn delete_breakpoint(&mut self, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            self.breakpoints.clear();
+            self.opcode_breakpoints.clear();
+            self.vm.clear_breakpoints();
+            return Ok("deleted all breakpoints\n".to_string());
+        }
+        let removed = match parse_location(args)? {
+            Location::Addr(addr) => {
+                self.breakpoints.remove(&addr);
+                self.vm.remove_breakpoint(addr)
+            }
+            Location::Opcode(op) => {
+                self.opcode_breakpoints.retain(|o| *o != op);
+                self.vm.remove_opcode_breakpoint(op)
+            }
+        };
+        if removed {
+            Ok(format!("deleted breakpoint {}\n", args))
+        } else {
+            Err(format!("no breakpoint {}", args))
+        }
+    }
+
+    fn info(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "pc {}, relative base {}, {} instructions executed",
+            self.vm.pc(),
+            self.vm.relative_base(),
+            self.vm.instructions_executed()
+        )
+        .unwrap();
+        if self.vm.halted() {
+            writeln!(out, "halted").unwrap();
+        }
+        let mut breakpoints: Vec<String> =
+            self.breakpoints.iter().map(|b| format!("{:04}", b)).collect();
+        breakpoints.extend(self.opcode_breakpoints.iter().map(|o| o.mnemonic().to_string()));
+        if !breakpoints.is_empty() {
+            writeln!(out, "breakpoints: {}", breakpoints.join(", ")).unwrap();
+        }
+        writeln!(
+            out,
+            "{} queued inputs, {} collected outputs",
+            self.vm.pending_input().len(),
+            self.outputs.len()
+        )
+        .unwrap();
+        self.show_current(&mut out);
+        out
+    }
+
+    fn mem(&self, args: &str) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        let start: usize = parse(words.next().ok_or("mem needs an address")?)?;
+        let count = match words.next() {
+            Some(n) => parse(n)?,
+            None => WORDS_PER_LINE,
+        };
+        if count > MAX_WORDS {
+            return Err(format!("mem shows at most {} words at a time", MAX_WORDS));
+        }
+        let end = start
+            .checked_add(count)
+            .ok_or("range runs past the end of memory")?;
+        let mut out = String::new();
+        for line_start in (start..end).step_by(WORDS_PER_LINE) {
+            let end = line_start.saturating_add(WORDS_PER_LINE).min(end);
+            let values: Vec<String> = (line_start..end)
+                .map(|a| self.vm.get_state(a).to_string())
+                .collect();
+            writeln!(out, "{:04}: {}", line_start, values.join(" ")).unwrap();
+        }
+        Ok(out)
+    }
+
+    fn set(&mut self, args: &str) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        let start: usize = parse(words.next().ok_or("set needs an address")?)?;
+        let values = words.map(parse).collect::<Result<Vec<i64>, _>>()?;
+        if values.is_empty() {
+            return Err("set needs at least one value".to_string());
+        }
+        if start.checked_add(values.len()).is_none() {
+            return Err("values run past the end of memory".to_string());
+        }
+        for (i, v) in values.iter().enumerate() {
+            self.vm.set_state(start + i, *v);
+        }
+        Ok(format!("wrote {} words at {:04}\n", values.len(), start))
+    }
+
+    fn relative_base(&mut self, args: &str) -> Result<String, String> {
+        if !args.is_empty() {
+            self.vm.set_relative_base(parse(args)?);
+        }
+        Ok(format!("relative base {}\n", self.vm.relative_base()))
+    }
+
+    fn input(&mut self, args: &str) -> Result<String, String> {
+        let values = args
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(parse)
+            .collect::<Result<Vec<i64>, _>>()?;
+        for v in &values {
+            self.vm.push_input(*v);
+        }
+        Ok(format!(
+            "queued {} inputs, {} pending\n",
+            values.len(),
+            self.vm.pending_input().len()
+        ))
+    }
+
+    fn output(&mut self) -> String {
+        if self.outputs.is_empty() {
+            return "no outputs\n".to_string();
+        }
+        // Text programs are much easier to follow as text
+        let mut out = if self.outputs.iter().all(|v| (1..128).contains(v)) {
+            self.outputs.iter().map(|v| *v as u8 as char).collect()
+        } else {
+            let values: Vec<String> = self.outputs.iter().map(|v| v.to_string()).collect();
+            values.join(",")
+        };
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        self.outputs.clear();
+        out
+    }
+
+    fn disasm(&self, args: &str) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        let target = match words.next() {
+            Some(a) => parse(a)?,
+            None => self.vm.pc(),
+        };
+        let count: usize = match words.next() {
+            Some(n) => parse(n)?,
+            None => 12,
+        };
+
+        // Disassemble a snapshot of memory so patched and self-modified code
+        // shows up as it is now. Past the program only a window around the
+        // target is read, since it can be anywhere.
+        let (base, end) = if target < self.program.len().saturating_add(DISASM_WINDOW) {
+            (0, self.program.len().max(target + DISASM_WINDOW))
+        } else {
+            (target - DISASM_WINDOW, target.saturating_add(DISASM_WINDOW))
+        };
+        let memory: Vec<i64> = (base..end).map(|a| self.vm.get_state(a)).collect();
+        let entry_points: Vec<usize> = [0, target, self.vm.pc()]
+            .iter()
+            .filter(|a| (base..end).contains(*a))
+            .map(|a| a - base)
+            .collect();
+        let mut lines = disasm::disassemble_from(&memory, &entry_points);
+        for line in &mut lines {
+            match line {
+                Line::Code { addr, .. } | Line::Data { addr, .. } | Line::Unknown { addr, .. } => {
+                    *addr += base
+                }
+            }
+        }
+        let at = lines
+            .iter()
+            .position(|l| l.addr() <= target && target < l.addr() + l.words().len())
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        for line in lines.iter().skip(at.saturating_sub(DISASM_CONTEXT)).take(count) {
+            let addr = line.addr();
+            let marker = if addr == self.vm.pc() && matches!(line, Line::Code { .. }) {
+                "=>"
+            } else if self.breakpoints.contains(&addr) {
+                " *"
+            } else {
+                "  "
+            };
+            writeln!(out, "{} {}", marker, line).unwrap();
+        }
+        Ok(out)
+    }
+
+    fn trace(&mut self, args: &str) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        let path = words.next().ok_or("trace needs a file name or `off`")?;
+        // Finish any trace already running so it's flushed to disk
+        match self.vm.detach_tracer() {
+            Some(tracer) => tracer.finish().map_err(|e| e.to_string())?,
+            None if path == "off" => return Err("not tracing".to_string()),
+            None => {}
+        }
+        if path == "off" {
+            return Ok("stopped tracing\n".to_string());
+        }
+        let mut tracer = Tracer::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        for word in words {
+            tracer = match (word.find(".."), Opcode::from_mnemonic(word)) {
+                (Some(i), _) => tracer.pc_range(parse(&word[..i])?..parse(&word[i + 2..])?),
+                (None, Some(op)) => tracer.opcode(op),
+                (None, None) => return Err(format!("expected `A..B` or a mnemonic, found `{}`", word)),
+            };
+        }
+        self.vm.attach_tracer(tracer);
+        Ok(format!("tracing to {}\n", path))
+    }
+
+    fn profile(&mut self, args: &str) -> Result<String, String> {
+        let mut words = args.split_whitespace();
+        match words.next() {
+            Some("on") => {
+                if self.vm.profiler().is_none() {
+                    self.vm.attach_profiler(Profiler::new());
+                }
+                Ok("profiling\n".to_string())
+            }
+            Some("off") => match self.vm.detach_profiler() {
+                Some(_) => Ok("stopped profiling\n".to_string()),
+                None => Err("not profiling".to_string()),
+            },
+            Some("csv") => {
+                let path = words.next().ok_or("profile csv needs a file name")?;
+                let profiler = self.vm.profiler().ok_or("not profiling")?;
+                std::fs::write(path, profiler.to_csv(self.vm.memory()))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                Ok(format!("wrote {}\n", path))
+            }
+            top => {
+                let top = parse_or(top.unwrap_or(""), 20)?;
+                let profiler = self.vm.profiler().ok_or("not profiling, try `profile on`")?;
+                Ok(profiler.report(self.vm.memory(), top))
+            }
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<String, String> {
+        if path.is_empty() {
+            return Err("save needs a file name".to_string());
+        }
+        // Outputs the debugger collected are part of the machine's state too
+        let mut snapshot = self.vm.snapshot();
+        snapshot.output.extend(&self.outputs);
+        snapshot.save(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(format!("saved to {}\n", path))
+    }
+
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        if path.is_empty() {
+            return Err("load needs a file name".to_string());
+        }
+        let snapshot = Snapshot::load(path).map_err(|e| format!("{}: {}", path, e))?;
+        self.vm.restore(&snapshot);
+        self.outputs = self.vm.take_output();
+        let mut out = format!("loaded {}\n", path);
+        self.show_current(&mut out);
+        Ok(out)
+    }
+
+    fn reset(&mut self) -> String {
+        let tracer = self.vm.detach_tracer();
+        let profiling = self.vm.profiler().is_some();
+        self.vm = VM::new(self.program.clone());
+        if let Some(tracer) = tracer {
+            self.vm.attach_tracer(tracer);
+        }
+        // Counts from the previous run don't describe the new one
+        if profiling {
+            self.vm.attach_profiler(Profiler::new());
+        }
+        for addr in &self.breakpoints {
+            self.vm.add_breakpoint(*addr);
+        }
+        for op in &self.opcode_breakpoints {
+            self.vm.add_opcode_breakpoint(*op);
+        }
+        self.outputs.clear();
+        format!("reset\n{}\n", self.current())
+    }
+}
+
+enum Location {
+    Addr(usize),
+    Opcode(Opcode),
+}
+
+fn parse_location(s: &str) -> Result<Location, String> {
+    if s.is_empty() {
+        return Err("expected an address or a mnemonic".to_string());
+    }
+    match Opcode::from_mnemonic(s) {
+        Some(op) => Ok(Location::Opcode(op)),
+        None => parse(s).map(Location::Addr),
+    }
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.trim().parse().map_err(|_| format!("invalid number `{}`", s.trim()))
+}
+
+fn parse_or<T: std::str::FromStr>(s: &str, default: T) -> Result<T, String> {
+    if s.is_empty() {
+        Ok(default)
+    } else {
+        parse(s)
+    }
+}
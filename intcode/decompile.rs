@@ -0,0 +1,989 @@
+//! Turns compiled Intcode back into structured pseudocode.
+//!
+//! The AoC programs were compiled with a consistent calling convention, and
+//! the decompiler leans on it:
+//!
+//! * a call stores its return address in `[rb+0]`, its arguments in
+//!   `[rb+1]`, `[rb+2]`, ... and jumps unconditionally to the function;
+//! * a function starts with `ARB #n` to reserve a frame of `n` words, so the
+//!   return address ends up at `[rb-n]` and its arguments at `[rb-n+1]` and
+//!   up; it returns with `ARB #-n` and a jump to `[rb+0]`;
+//! * results come back in the first argument slot, which the caller sees as
+//!   `out1`;
+//! * conditions are computed with `LT` or `EQ` into a scratch word that only
+//!   a following `JZ`/`JNZ` reads;
+//! * arrays are indexed, and function pointers called, by writing the
+//!   address into an operand of a later instruction.
+//!
+//! Frame words are named `arg<N>` when the function reads them before
+//! writing them and `var<N>` otherwise, where `N` is the offset from the
+//! return address. Words a function sets up for a call are `out<N>`, other
+//! memory is `mem[..]`, and an argument the caller left as it was is `?`.
+//! Control flow is rebuilt into `if`/`else`, `while`, `do`/`while` and
+//! `loop`, with `goto` for whatever doesn't fit:
+//!
+//! ```
+//! use intcode::{asm, decompile};
+//!
+//! let program = asm::assemble(
+//!     "
+//!             ARB #100
+//!             IN -> [rb+1]
+//!             ADD #ret, #0 -> [rb+0]
+//!             JNZ #1, #sum
+//!     ret:    OUT [rb+1]
+//!             HLT
+//!     ; adds up 1 to n
+//!     sum:    ARB #4
+//!             ADD #0, #0 -> [rb-2]
+//!     loop:   LT #0, [rb-3] -> [rb-1]
+//!             JZ [rb-1], #done
+//!             ADD [rb-2], [rb-3] -> [rb-2]
+//!             ADD [rb-3], #-1 -> [rb-3]
+//!             JNZ #1, #loop
+//!     done:   ADD [rb-2], #0 -> [rb-3]
+//!             ARB #-4
+//!             JNZ #1, [rb+0]
+//!     ",
+//! )
+//! .unwrap();
+//! assert_eq!(
+//!     decompile::to_text(&decompile::decompile(&program)),
+//!     "\
+//! fn main() {
+//!     rb += 100;
+//!     f14(input());
+//!     output(out1);
+//!     halt;
+//! }
+//!
+//! fn f14(arg1) {
+//!     var2 = 0;
+//!     while (0 < arg1) {
+//!         var2 = var2 + arg1;
+//!         arg1 = arg1 - 1;
+//!     }
+//!     return var2;
+//! }
+//! "
+//! );
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::cfg::{Block, Cfg, Observed};
+use crate::instruction::{Instruction, Mode, Opcode, Param};
+
+/// A decompiled function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub name: String,
+    /// Words reserved by the `ARB` on entry, including the return address.
+    pub frame: i64,
+    pub params: Vec<String>,
+    /// Pseudocode of the body, indented one level.
+    pub body: String,
+}
+
+/// Formats as `fn name(params) {`, the body and a closing brace.
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "fn {}({}) {{", self.name, self.params.join(", "))?;
+        write!(f, "{}", self.body)?;
+        writeln!(f, "}}")
+    }
+}
+
+/// Decompiles every function reachable from address 0, which becomes `main`,
+/// ordered by address.
+pub fn decompile(program: &[i64]) -> Vec<Function> {
+    decompile_observed(program, &Observed::default())
+}
+
+/// Like `decompile`, but also follows what a run was `observed` doing (see
+/// `cfg::observe`), for code that's only reached through memory or that the
+/// program writes for itself.
+pub fn decompile_observed(program: &[i64], observed: &Observed) -> Vec<Function> {
+    let cfg = Cfg::build(program, observed);
+    let blocks: BTreeMap<usize, &Block> = cfg.blocks.iter().map(|b| (b.start, b)).collect();
+
+    let mut entries = BTreeSet::new();
+    entries.insert(0);
+    for block in blocks.values() {
+        if let Some(target) = call_target(block) {
+            entries.insert(target);
+        }
+    }
+    let names: HashMap<usize, String> = entries
+        .iter()
+        .map(|e| {
+            (
+                *e,
+                if *e == 0 {
+                    "main".to_string()
+                } else {
+                    format!("f{}", e)
+                },
+            )
+        })
+        .collect();
+
+    let analyses: Vec<Analysis> = entries
+        .iter()
+        .filter(|e| blocks.contains_key(e))
+        .map(|entry| Analysis::new(&cfg, &blocks, *entry))
+        .collect();
+    let arities: HashMap<usize, usize> = analyses.iter().map(|a| (a.entry, a.arity())).collect();
+    analyses
+        .iter()
+        .map(|a| decompile_function(&cfg, a, &names, &arities))
+        .collect()
+}
+
+/// All functions as one listing.
+pub fn to_text(functions: &[Function]) -> String {
+    let texts: Vec<String> = functions.iter().map(|f| f.to_string()).collect();
+    texts.join("\n")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Mul,
+    Lt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Mul => "*",
+            Op::Lt => "<",
+            Op::Ge => ">=",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        !matches!(self, Op::Add | Op::Mul)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Mem(Box<Expr>),
+    Input,
+    Bin(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn bin(op: Op, a: Expr, b: Expr) -> Expr {
+        match (op, a, b) {
+            (Op::Add, Expr::Num(x), Expr::Num(y)) => Expr::Num(x.wrapping_add(y)),
+            (Op::Mul, Expr::Num(x), Expr::Num(y)) => Expr::Num(x.wrapping_mul(y)),
+            (Op::Add, Expr::Num(0), e) | (Op::Add, e, Expr::Num(0)) => e,
+            (Op::Mul, Expr::Num(1), e) | (Op::Mul, e, Expr::Num(1)) => e,
+            (Op::Mul, Expr::Num(0), _) | (Op::Mul, _, Expr::Num(0)) => Expr::Num(0),
+            // Constants read better on the right of a sum
+            (Op::Add, n @ Expr::Num(_), e) => Expr::Bin(Op::Add, Box::new(e), Box::new(n)),
+            (op, a, b) => Expr::Bin(op, Box::new(a), Box::new(b)),
+        }
+    }
+
+    // The condition under which a jump on this value is taken, for `JNZ`
+    fn truthy(self) -> Expr {
+        match self {
+            e @ Expr::Bin(op, ..) if op.is_comparison() => e,
+            e => Expr::Bin(Op::Ne, Box::new(e), Box::new(Expr::Num(0))),
+        }
+    }
+
+    fn negate(self) -> Expr {
+        match self {
+            Expr::Bin(op, a, b) if op.is_comparison() => {
+                let op = match op {
+                    Op::Lt => Op::Ge,
+                    Op::Ge => Op::Lt,
+                    Op::Eq => Op::Ne,
+                    _ => Op::Eq,
+                };
+                Expr::Bin(op, a, b)
+            }
+            e => Expr::Bin(Op::Eq, Box::new(e), Box::new(Expr::Num(0))),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(n) => write!(f, "{}", n),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Mem(addr) => write!(f, "mem[{}]", addr),
+            Expr::Input => write!(f, "input()"),
+            Expr::Bin(op, a, b) => {
+                let side = |e: &Expr| match e {
+                    Expr::Bin(..) => format!("({})", e),
+                    e => e.to_string(),
+                };
+                match (op, &**b) {
+                    (Op::Add, Expr::Num(n)) if *n < 0 => {
+                        write!(f, "{} - {}", side(a), n.unsigned_abs())
+                    }
+                    (Op::Mul, Expr::Num(-1)) => write!(f, "-{}", side(a)),
+                    _ => write!(f, "{} {} {}", side(a), op.symbol(), side(b)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    Assign {
+        dest: Expr,
+        value: Expr,
+        // `[rb+N]` with `N` positive, i.e. set up for a call
+        out: Option<i64>,
+    },
+    Line(String),
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Assign { dest, value, .. } => write!(f, "{} = {};", dest, value),
+            Stmt::Line(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+// How control leaves a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Exit {
+    Fall,
+    Goto(usize),
+    // Jumps to the target when the condition holds, falls through otherwise
+    Branch(Expr, usize),
+    Indirect(Expr),
+    Return,
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+struct Lowered {
+    end: usize,
+    stmts: Vec<Stmt>,
+    exit: Exit,
+}
+
+fn constant(instruction: &Instruction) -> Option<i64> {
+    let p = &instruction.params;
+    if p.len() < 2 || p[0].mode != Mode::Immediate || p[1].mode != Mode::Immediate {
+        return None;
+    }
+    match instruction.opcode {
+        Opcode::Add => Some(p[0].value.wrapping_add(p[1].value)),
+        Opcode::Mul => Some(p[0].value.wrapping_mul(p[1].value)),
+        _ => None,
+    }
+}
+
+// Whether the instruction is a jump that's always taken
+fn unconditional(instruction: &Instruction) -> bool {
+    let p = &instruction.params;
+    match instruction.opcode {
+        Opcode::JumpIfTrue => p[0].mode == Mode::Immediate && p[0].value != 0,
+        Opcode::JumpIfFalse => p[0].mode == Mode::Immediate && p[0].value == 0,
+        _ => false,
+    }
+}
+
+fn writes_to(instruction: &Instruction, mode: Mode, value: i64) -> bool {
+    instruction.opcode.writes()
+        && instruction
+            .params
+            .last()
+            .is_some_and(|p| p.mode == mode && p.value == value)
+}
+
+fn reads(instruction: &Instruction, mode: Mode, value: i64) -> bool {
+    let n = instruction.params.len();
+    instruction.params.iter().enumerate().any(|(j, p)| {
+        p.mode == mode && p.value == value && !(instruction.opcode.writes() && j == n - 1)
+    })
+}
+
+// Index of the instruction storing the return address for a call at the end
+// of the block
+fn return_store(block: &Block) -> Option<usize> {
+    let ret = block.end as i64;
+    block
+        .instructions
+        .iter()
+        .rposition(|(_, i)| writes_to(i, Mode::Relative, 0) && constant(i) == Some(ret))
+}
+
+// Where the function a block ends by calling is: a store of the address
+// after the block into `[rb+0]` followed by an unconditional jump
+fn call(block: &Block) -> Option<Param> {
+    let (_, last) = block.instructions.last().unwrap();
+    if unconditional(last) && return_store(block).is_some() {
+        Some(last.params[1])
+    } else {
+        None
+    }
+}
+
+fn call_target(block: &Block) -> Option<usize> {
+    match call(block) {
+        Some(Param {
+            mode: Mode::Immediate,
+            value,
+        }) if value >= 0 => Some(value as usize),
+        _ => None,
+    }
+}
+
+// An unconditional jump to `[rb+0]`
+fn is_return(instruction: &Instruction) -> bool {
+    unconditional(instruction)
+        && instruction.params[1].mode == Mode::Relative
+        && instruction.params[1].value == 0
+}
+
+// Where a block can go within its function
+fn successors(cfg: &Cfg, block: &Block) -> Vec<usize> {
+    let (_, last) = block.instructions.last().unwrap();
+    if call(block).is_some() {
+        return vec![block.end];
+    }
+    if is_return(last) {
+        return Vec::new();
+    }
+    cfg.edges
+        .iter()
+        .filter(|e| e.from == block.start)
+        .map(|e| e.to)
+        .collect()
+}
+
+// A function's blocks and frame, worked out before any of it is lowered so
+// that callers know how many arguments it takes
+struct Analysis<'a> {
+    entry: usize,
+    // Blocks reachable from the entry without following calls, by address
+    blocks: BTreeMap<usize, &'a Block>,
+    frame: i64,
+    // Frame offsets, from the return address, read before they're written
+    args: BTreeSet<i64>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(cfg: &Cfg, blocks: &BTreeMap<usize, &'a Block>, entry: usize) -> Analysis<'a> {
+        let mut own = BTreeMap::new();
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            if let Some(block) = blocks.get(&start) {
+                if own.insert(start, *block).is_none() {
+                    work.extend(successors(cfg, block));
+                }
+            }
+        }
+
+        // Address 0's `ARB` sets up the stack rather than a frame
+        let first = &blocks[&entry].instructions[0].1;
+        let frame = match (first.opcode, first.params.first()) {
+            (Opcode::AdjustBase, Some(p))
+                if entry != 0 && p.mode == Mode::Immediate && p.value > 0 =>
+            {
+                p.value
+            }
+            _ => 0,
+        };
+
+        let mut args = BTreeSet::new();
+        let mut seen = BTreeSet::new();
+        for block in own.values() {
+            for (_, ins) in &block.instructions {
+                let n = ins.params.len();
+                for (j, p) in ins.params.iter().enumerate() {
+                    let offset = p.value + frame;
+                    // Only the first use of each word counts
+                    let first = p.mode == Mode::Relative
+                        && offset > 0
+                        && offset < frame
+                        && seen.insert(offset);
+                    if first && !(ins.opcode.writes() && j == n - 1) {
+                        args.insert(offset);
+                    }
+                }
+            }
+        }
+        Analysis {
+            entry,
+            blocks: own,
+            frame,
+            args,
+        }
+    }
+
+    fn arity(&self) -> usize {
+        self.args.iter().next_back().map_or(0, |o| *o as usize)
+    }
+
+    // Whether the word at (mode, value) is written before it's read again on
+    // every path out of `block`
+    fn dead_after(&self, cfg: &Cfg, block: &Block, mode: Mode, value: i64) -> bool {
+        // The caller and callees can see globals and the words above the
+        // frame, the caller reads the first one as the result
+        let shared = mode == Mode::Position || value >= 1;
+        let result = mode == Mode::Relative && value + self.frame == 1;
+        let mut work = successors(cfg, block);
+        let mut seen = BTreeSet::new();
+        'paths: while let Some(start) = work.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            let b = match self.blocks.get(&start) {
+                Some(b) => b,
+                None => return false,
+            };
+            for (_, ins) in &b.instructions {
+                if reads(ins, mode, value) {
+                    return false;
+                }
+                if writes_to(ins, mode, value) {
+                    continue 'paths;
+                }
+            }
+            let (_, last) = b.instructions.last().unwrap();
+            if (shared && call(b).is_some()) || ((shared || result) && is_return(last)) {
+                return false;
+            }
+            work.extend(successors(cfg, b));
+        }
+        true
+    }
+}
+
+struct Context<'a> {
+    names: &'a HashMap<usize, String>,
+    arities: &'a HashMap<usize, usize>,
+    frame: i64,
+    args: &'a BTreeSet<i64>,
+    // Blocks whose conditional jump tests a comparison made just before it
+    // into a word that's dead afterwards
+    folds: BTreeSet<usize>,
+    // Blocks that calls return to, which start with the result in `out1`
+    returns: BTreeSet<usize>,
+}
+
+impl Context<'_> {
+    fn relative(&self, k: i64) -> String {
+        let offset = k + self.frame;
+        if k >= 1 {
+            format!("out{}", k)
+        } else if offset == 0 {
+            "ret_addr".to_string()
+        } else if offset < 0 {
+            format!("rb[{}]", k)
+        } else if self.args.contains(&offset) {
+            format!("arg{}", offset)
+        } else {
+            format!("var{}", offset)
+        }
+    }
+
+    fn operand(&self, p: &Param) -> Expr {
+        match p.mode {
+            Mode::Immediate => Expr::Num(p.value),
+            Mode::Position => Expr::Mem(Box::new(Expr::Num(p.value))),
+            Mode::Relative => Expr::Var(self.relative(p.value)),
+        }
+    }
+
+    // Takes the assignments to `out1`, `out2`, ... that end `stmts` as the
+    // arguments of a call to a function taking `arity` of them, or as many as
+    // there are if that's unknown. Slots the caller didn't set just before
+    // the call hold whatever was left there, shown as `?`, except for the
+    // result of a call the block starts after.
+    fn call_args(&self, block: &Block, stmts: &mut Vec<Stmt>, arity: Option<usize>) -> Vec<Expr> {
+        let limit = arity.map_or(i64::MAX, |a| a as i64);
+        let mut slots = BTreeMap::new();
+        for stmt in stmts.iter().rev() {
+            match stmt {
+                Stmt::Assign {
+                    out: Some(k),
+                    value,
+                    ..
+                } if *k <= limit && !slots.contains_key(k) => {
+                    slots.insert(*k, value.clone());
+                }
+                _ => break,
+            }
+        }
+        stmts.truncate(stmts.len() - slots.len());
+        let after_call = stmts.is_empty() && self.returns.contains(&block.start);
+        let count = arity
+            .unwrap_or(0)
+            .max(slots.keys().next_back().map_or(0, |k| *k as usize));
+        (1..=count as i64)
+            .map(|k| match slots.remove(&k) {
+                Some(value) => value,
+                None if k == 1 && after_call => Expr::Var("out1".to_string()),
+                None => Expr::Var("?".to_string()),
+            })
+            .collect()
+    }
+
+    fn lower(&self, block: &Block, is_entry: bool) -> Lowered {
+        let insts = &block.instructions;
+        let call = call(block);
+        let ret_store = call.and_then(|_| return_store(block));
+        // The word a function returns its result in
+        let result = Expr::Var(self.relative(1 - self.frame));
+        // Operands patched by the instruction before, keyed by
+        // (instruction index, parameter index)
+        let mut patched: HashMap<(usize, usize), Expr> = HashMap::new();
+        let mut stmts = Vec::new();
+        let mut exit = Exit::Fall;
+
+        for (idx, (_, ins)) in insts.iter().enumerate() {
+            let ops: Vec<Expr> = (0..ins.params.len())
+                .map(|j| {
+                    patched
+                        .remove(&(idx, j))
+                        .unwrap_or_else(|| self.operand(&ins.params[j]))
+                })
+                .collect();
+            let param = |j: usize| ops[j].clone();
+            match ins.opcode {
+                Opcode::AdjustBase if ins.params[0].mode == Mode::Immediate => {
+                    let n = ins.params[0].value;
+                    let prologue = is_entry && idx == 0 && n == self.frame;
+                    let epilogue = self.frame > 0
+                        && n == -self.frame
+                        && insts.get(idx + 1).is_some_and(|(_, i)| is_return(i));
+                    if !prologue && !epilogue {
+                        stmts.push(Stmt::Line(format!("rb += {};", n)));
+                    }
+                }
+                Opcode::AdjustBase => stmts.push(Stmt::Line(format!("rb += {};", param(0)))),
+                Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals | Opcode::Input => {
+                    if Some(idx) == ret_store {
+                        continue;
+                    }
+                    let value = match ins.opcode {
+                        Opcode::Add => Expr::bin(Op::Add, param(0), param(1)),
+                        Opcode::Mul => Expr::bin(Op::Mul, param(0), param(1)),
+                        Opcode::LessThan => Expr::bin(Op::Lt, param(0), param(1)),
+                        Opcode::Equals => Expr::bin(Op::Eq, param(0), param(1)),
+                        _ => Expr::Input,
+                    };
+                    let target = ins.params.len() - 1;
+                    let dest = ins.params[target];
+                    // Writing into an operand of a later instruction is how
+                    // compiled code indexes memory and calls through pointers
+                    let operand =
+                        insts
+                            .iter()
+                            .enumerate()
+                            .skip(idx + 1)
+                            .find_map(|(k, (addr, i))| {
+                                let word = dest.value - *addr as i64 - 1;
+                                if word >= 0 && (word as usize) < i.params.len() {
+                                    Some((k, word as usize, i.params[word as usize].mode))
+                                } else {
+                                    None
+                                }
+                            });
+                    if let (Mode::Position, Some((k, word, mode))) = (dest.mode, operand) {
+                        let patch = match mode {
+                            Mode::Position => Some(Expr::Mem(Box::new(value.clone()))),
+                            Mode::Immediate => Some(value.clone()),
+                            Mode::Relative => None,
+                        };
+                        if let Some(patch) = patch {
+                            patched.insert((k, word), patch);
+                            continue;
+                        }
+                    }
+                    let dest_expr = param(target);
+                    // Copying the result onto itself still says what's returned
+                    if dest_expr == value && dest_expr != result {
+                        continue;
+                    }
+                    let out = match dest.mode {
+                        Mode::Relative if dest.value >= 1 => Some(dest.value),
+                        _ => None,
+                    };
+                    stmts.push(Stmt::Assign {
+                        dest: dest_expr,
+                        value,
+                        out,
+                    });
+                }
+                Opcode::Output => stmts.push(Stmt::Line(format!("output({});", param(0)))),
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                    if let Some(target) = call {
+                        let direct = match (target.mode, &ops[1]) {
+                            (Mode::Immediate, Expr::Num(n)) if *n == target.value => {
+                                Some(*n as usize)
+                            }
+                            _ => None,
+                        };
+                        let arity = direct.and_then(|t| self.arities.get(&t).copied());
+                        let args: Vec<String> = self
+                            .call_args(block, &mut stmts, arity)
+                            .iter()
+                            .map(|a| a.to_string())
+                            .collect();
+                        let callee = match direct {
+                            Some(t) => self.names[&t].clone(),
+                            None => format!("(*{})", param(1)),
+                        };
+                        stmts.push(Stmt::Line(format!("{}({});", callee, args.join(", "))));
+                        continue;
+                    }
+                    let taken = if unconditional(ins) {
+                        None
+                    } else {
+                        let mut value = param(0);
+                        if self.folds.contains(&block.start) {
+                            if let Some(Stmt::Assign { value: cmp, .. }) = stmts.pop() {
+                                value = cmp;
+                            }
+                        }
+                        Some(if ins.opcode == Opcode::JumpIfTrue {
+                            value.truthy()
+                        } else {
+                            value.truthy().negate()
+                        })
+                    };
+                    let target = match ins.params[1] {
+                        Param {
+                            mode: Mode::Immediate,
+                            value,
+                        } if value >= 0 => Some(value as usize),
+                        _ => None,
+                    };
+                    exit = match (taken, target) {
+                        (None, Some(t)) => Exit::Goto(t),
+                        (Some(c), Some(t)) => Exit::Branch(c, t),
+                        (None, None) if is_return(ins) => {
+                            match stmts.last() {
+                                Some(Stmt::Assign { dest, .. }) if *dest == result => {
+                                    if let Some(Stmt::Assign { value, .. }) = stmts.pop() {
+                                        stmts.push(Stmt::Line(format!("return {};", value)));
+                                    }
+                                }
+                                _ => stmts.push(Stmt::Line("return;".to_string())),
+                            }
+                            Exit::Return
+                        }
+                        (None, None) => Exit::Indirect(param(1)),
+                        (Some(c), None) => {
+                            stmts.push(Stmt::Line(format!("if ({}) goto *{};", c, param(1))));
+                            Exit::Fall
+                        }
+                    };
+                }
+                Opcode::Halt => exit = Exit::Halt,
+            }
+        }
+        Lowered {
+            end: block.end,
+            stmts,
+            exit,
+        }
+    }
+}
+
+fn decompile_function(
+    cfg: &Cfg,
+    analysis: &Analysis,
+    names: &HashMap<usize, String>,
+    arities: &HashMap<usize, usize>,
+) -> Function {
+    let entry = analysis.entry;
+    let mut ctx = Context {
+        names,
+        arities,
+        frame: analysis.frame,
+        args: &analysis.args,
+        folds: BTreeSet::new(),
+        returns: analysis
+            .blocks
+            .values()
+            .filter(|b| call(b).is_some())
+            .map(|b| b.end)
+            .collect(),
+    };
+    for block in analysis.blocks.values() {
+        let insts = &block.instructions;
+        if let [.., (_, cmp), (_, jump)] = &insts[..] {
+            let p = jump.params.first().copied();
+            let foldable = jump.opcode.is_jump()
+                && !unconditional(jump)
+                && matches!(cmp.opcode, Opcode::LessThan | Opcode::Equals)
+                && p.is_some_and(|p| {
+                    writes_to(cmp, p.mode, p.value)
+                        && analysis.dead_after(cfg, block, p.mode, p.value)
+                });
+            if foldable {
+                ctx.folds.insert(block.start);
+            }
+        }
+    }
+
+    let mut lowered: BTreeMap<usize, Lowered> = analysis
+        .blocks
+        .values()
+        .map(|b| (b.start, ctx.lower(b, b.start == entry)))
+        .collect();
+    // Nothing is known about what runs after a block that falls into words
+    // that don't decode, so say so rather than end the function there
+    for block in lowered.values_mut() {
+        if matches!(block.exit, Exit::Fall) && cfg.block(block.end).is_none() {
+            let line = format!("// runs on into data at {}", block.end);
+            block.stmts.push(Stmt::Line(line));
+        }
+    }
+    let first = *lowered.keys().next().unwrap();
+    let mut emitter = Emitter {
+        blocks: &lowered,
+        labels: BTreeSet::new(),
+        used: BTreeSet::new(),
+        out: String::new(),
+    };
+    let scope = Scope {
+        end: usize::MAX,
+        follow: None,
+        loop_: None,
+        open_header: None,
+        skip_exit: None,
+    };
+    // The first pass finds which labels are jumped to
+    for pass in 0..2 {
+        emitter.out.clear();
+        if first != entry {
+            let jump = emitter.jump(entry, scope);
+            emitter.line(1, &jump);
+        }
+        emitter.emit(1, first, scope);
+        if pass == 0 {
+            emitter.labels = std::mem::take(&mut emitter.used);
+        }
+    }
+
+    Function {
+        entry,
+        name: names[&entry].clone(),
+        frame: analysis.frame,
+        params: analysis.args.iter().map(|o| format!("arg{}", o)).collect(),
+        body: emitter.out,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scope {
+    // Blocks at or after this belong to an enclosing construct
+    end: usize,
+    // Where control goes after the scope, so a jump there at the end of it
+    // needs no statement
+    follow: Option<usize>,
+    // Header and exit of the innermost loop
+    loop_: Option<(usize, usize)>,
+    // Loop header being emitted, which mustn't start the same loop again
+    open_header: Option<usize>,
+    // Block whose exit the enclosing construct renders
+    skip_exit: Option<usize>,
+}
+
+struct Emitter<'a> {
+    blocks: &'a BTreeMap<usize, Lowered>,
+    labels: BTreeSet<usize>,
+    used: BTreeSet<usize>,
+    out: String,
+}
+
+impl Emitter<'_> {
+    fn line(&mut self, indent: usize, text: &str) {
+        writeln!(self.out, "{}{}", "    ".repeat(indent), text).unwrap();
+    }
+
+    fn label(&mut self, indent: usize, addr: usize) {
+        if self.labels.contains(&addr) {
+            self.line(indent.saturating_sub(1), &format!("L{}:", addr));
+        }
+    }
+
+    fn jump(&mut self, target: usize, scope: Scope) -> String {
+        match scope.loop_ {
+            Some((header, _)) if header == target => "continue;".to_string(),
+            Some((_, exit)) if exit == target => "break;".to_string(),
+            _ => {
+                self.used.insert(target);
+                format!("goto L{};", target)
+            }
+        }
+    }
+
+    // End of the loop headed by `header`: the block furthest into the scope
+    // that jumps back to it
+    fn latch(&self, header: usize, end: usize) -> Option<usize> {
+        self.blocks
+            .range(header..end)
+            .filter(|(_, b)| match &b.exit {
+                Exit::Goto(t) | Exit::Branch(_, t) => *t == header,
+                _ => false,
+            })
+            .map(|(start, _)| *start)
+            .next_back()
+    }
+
+    fn emit(&mut self, indent: usize, start: usize, scope: Scope) {
+        let mut addr = start;
+        while let Some((&block_start, block)) = self.blocks.range(addr..scope.end).next() {
+            let block = block.clone();
+            let is_last = self.blocks.range(block.end..scope.end).next().is_none();
+
+            if scope.open_header != Some(block_start) {
+                if let Some(latch) = self.latch(block_start, scope.end) {
+                    addr = self.emit_loop(indent, block_start, latch);
+                    continue;
+                }
+            }
+
+            self.label(indent, block_start);
+            for stmt in &block.stmts {
+                self.line(indent, &stmt.to_string());
+            }
+            addr = block.end;
+            if scope.skip_exit == Some(block_start) {
+                continue;
+            }
+            match block.exit {
+                Exit::Fall => {}
+                // Lowering already wrote the `return` along with its value
+                Exit::Return => {}
+                Exit::Halt => self.line(indent, "halt;"),
+                Exit::Indirect(e) => self.line(indent, &format!("goto *{};", e)),
+                Exit::Goto(t) => {
+                    let natural = t == block.end || (is_last && scope.follow == Some(t));
+                    if !natural {
+                        let jump = self.jump(t, scope);
+                        self.line(indent, &jump);
+                    }
+                }
+                Exit::Branch(cond, t) if t > block_start && t >= block.end && t <= scope.end => {
+                    if t == block.end {
+                        continue;
+                    }
+                    // The then branch is what's skipped when the jump is taken.
+                    // If it ends by jumping over what follows, that's the else.
+                    let tail = self
+                        .blocks
+                        .range(block.end..t)
+                        .next_back()
+                        .map(|(s, b)| (*s, b.exit.clone()));
+                    let else_end = match tail {
+                        Some((_, Exit::Goto(e))) if e > t && e <= scope.end => Some(e),
+                        _ => None,
+                    };
+                    self.line(indent, &format!("if ({}) {{", cond.negate()));
+                    let follow = else_end.unwrap_or(t);
+                    self.emit(
+                        indent + 1,
+                        block.end,
+                        Scope {
+                            end: t,
+                            follow: Some(follow),
+                            open_header: None,
+                            skip_exit: None,
+                            ..scope
+                        },
+                    );
+                    if let Some(e) = else_end {
+                        self.line(indent, "} else {");
+                        self.emit(
+                            indent + 1,
+                            t,
+                            Scope {
+                                end: e,
+                                follow: Some(e),
+                                open_header: None,
+                                skip_exit: None,
+                                ..scope
+                            },
+                        );
+                        addr = e;
+                    } else {
+                        addr = t;
+                    }
+                    self.line(indent, "}");
+                }
+                Exit::Branch(cond, t) => {
+                    let jump = self.jump(t, scope);
+                    self.line(indent, &format!("if ({}) {}", cond, jump));
+                }
+            }
+        }
+    }
+
+    // Emits the loop from `header` to the block at `latch` and returns the
+    // address after it
+    fn emit_loop(&mut self, indent: usize, header: usize, latch: usize) -> usize {
+        let end = self.blocks[&latch].end;
+        let inner = Scope {
+            end,
+            follow: Some(header),
+            loop_: Some((header, end)),
+            open_header: Some(header),
+            skip_exit: None,
+        };
+        self.label(indent, header);
+        let head = self.blocks[&header].clone();
+        match (&head.exit, &self.blocks[&latch].exit) {
+            // Test at the top: `while`
+            (Exit::Branch(cond, exit), Exit::Goto(_))
+                if head.stmts.is_empty() && *exit == end && latch != header =>
+            {
+                let cond = cond.clone();
+                self.line(indent, &format!("while ({}) {{", cond.negate()));
+                self.emit(indent + 1, head.end, inner);
+                self.line(indent, "}");
+            }
+            // Test at the bottom: `do`/`while`
+            (_, Exit::Branch(cond, _)) => {
+                let cond = cond.clone();
+                self.line(indent, "do {");
+                self.emit(
+                    indent + 1,
+                    header,
+                    Scope {
+                        skip_exit: Some(latch),
+                        ..inner
+                    },
+                );
+                self.line(indent, &format!("}} while ({});", cond));
+            }
+            _ => {
+                self.line(indent, "loop {");
+                self.emit(indent + 1, header, inner);
+                self.line(indent, "}");
+            }
+        }
+        end
+    }
+}
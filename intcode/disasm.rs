@@ -0,0 +1,273 @@
+//! Turns an Intcode program into a readable listing.
+//!
+//! Code is found by following execution from address 0: fallthrough, jumps
+//! with immediate targets, and code addresses stored with the
+//! `ADD #addr, #0 -> [..]` idiom compiled programs use for return addresses.
+//! Everything that isn't reached is listed as data. `disassemble_with` also
+//! uses a `CodeMap` recorded while the program ran.
+
+use std::fmt;
+use std::fmt::Write;
+
+use crate::codemap::{CodeMap, Kind};
+use crate::instruction::{DecodeError, Instruction, Mode, Opcode};
+use crate::json;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code {
+        addr: usize,
+        instruction: Instruction,
+    },
+    /// A run of words that execution never reaches.
+    Data { addr: usize, values: Vec<i64> },
+    /// A word that execution reaches but that doesn't decode.
+    Unknown {
+        addr: usize,
+        value: i64,
+        error: DecodeError,
+    },
+}
+
+impl Line {
+    pub fn addr(&self) -> usize {
+        match self {
+            Line::Code { addr, .. } | Line::Data { addr, .. } | Line::Unknown { addr, .. } => {
+                *addr
+            }
+        }
+    }
+
+    /// The memory words this line covers.
+    pub fn words(&self) -> Vec<i64> {
+        match self {
+            Line::Code { instruction, .. } => instruction.encode(),
+            Line::Data { values, .. } => values.clone(),
+            Line::Unknown { value, .. } => vec![*value],
+        }
+    }
+}
+
+/// Formats as e.g. `0042: ADD [rb+3], #7 -> [120]`. Data and unknown words
+/// use the `DATA` directive so a listing can be fed back to the assembler.
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: ", self.addr())?;
+        match self {
+            Line::Code { instruction, .. } => write!(f, "{}", instruction),
+            Line::Data { values, .. } => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "DATA {}", values.join(", "))
+            }
+            Line::Unknown { value, error, .. } => write!(f, "DATA {} ; {}", value, error),
+        }
+    }
+}
+
+// Most data words per `DATA` line
+const DATA_PER_LINE: usize = 8;
+
+/// Disassembles `program`, discovering code by following execution from
+/// address 0.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    disassemble_from(program, &[0])
+}
+
+/// Like `disassemble`, but also follows execution from every address in
+/// `entry_points`, e.g. addresses a VM actually executed.
+pub fn disassemble_from(program: &[i64], entry_points: &[usize]) -> Vec<Line> {
+    disassemble_around(program, entry_points, vec![false; program.len()])
+}
+
+/// Disassembles `program` using what a VM found out about it while running:
+/// every instruction it executed is followed as an entry point, and words it
+/// only ever used as data are never decoded as code.
+pub fn disassemble_with(program: &[i64], map: &CodeMap) -> Vec<Line> {
+    let mut entry_points = vec![0];
+    entry_points.extend(map.instructions());
+    entry_points.retain(|a| *a < program.len());
+    let data = (0..program.len())
+        .map(|a| map.kind(a) == Some(Kind::Data))
+        .collect();
+    disassemble_around(program, &entry_points, data)
+}
+
+// Marking words as covered up front keeps them from being decoded
+fn disassemble_around(program: &[i64], entry_points: &[usize], mut covered: Vec<bool>) -> Vec<Line> {
+    let n = program.len();
+    let mut starts: Vec<Option<Instruction>> = vec![None; n];
+    let mut unknown: Vec<Option<DecodeError>> = vec![None; n];
+
+    // Entry points and jump targets are definitely code. Stored addresses are
+    // only a guess, so they're kept only if they decode cleanly up to the end
+    // of a block.
+    let mut work: Vec<(usize, bool)> = entry_points.iter().rev().map(|a| (*a, true)).collect();
+    while let Some((addr, certain)) = work.pop() {
+        let (chain, error) = decode_chain(program, &covered, addr);
+        if !certain && (error.is_some() || chain.is_empty()) {
+            continue;
+        }
+        if let Some((addr, e)) = error {
+            unknown[addr] = Some(e);
+        }
+        for (addr, instruction) in chain {
+            for c in &mut covered[addr..addr + instruction.size()] {
+                *c = true;
+            }
+            if let Some(target) = instruction.jump_target() {
+                work.push((target as usize, true));
+            }
+            if let Some(target) = stored_code_address(&instruction) {
+                work.push((target as usize, false));
+            }
+            starts[addr] = Some(instruction);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < n {
+        if let Some(instruction) = starts[addr].take() {
+            addr += instruction.size();
+            lines.push(Line::Code {
+                addr: addr - instruction.size(),
+                instruction,
+            });
+        } else if let Some(error) = unknown[addr] {
+            lines.push(Line::Unknown {
+                addr,
+                value: program[addr],
+                error,
+            });
+            addr += 1;
+        } else {
+            let start = addr;
+            while addr < n
+                && addr - start < DATA_PER_LINE
+                && starts[addr].is_none()
+                && unknown[addr].is_none()
+            {
+                addr += 1;
+            }
+            lines.push(Line::Data {
+                addr: start,
+                values: program[start..addr].to_vec(),
+            });
+        }
+    }
+    lines
+}
+
+type Chain = Vec<(usize, Instruction)>;
+
+// Decodes straight-line instructions from `addr` until the end of a block,
+// something already decoded, or a word that isn't an instruction
+fn decode_chain(
+    program: &[i64],
+    covered: &[bool],
+    mut addr: usize,
+) -> (Chain, Option<(usize, DecodeError)>) {
+    let mut chain = Vec::new();
+    while addr < program.len() && !covered[addr] {
+        let instruction = match Instruction::decode(program, addr) {
+            Ok(i) => i,
+            Err(e) => return (chain, Some((addr, e))),
+        };
+        let len = instruction.size();
+        if covered[addr..addr + len].iter().any(|c| *c) {
+            // Overlaps something already decoded, so it's not really code
+            break;
+        }
+        let ends_block = instruction.ends_block();
+        chain.push((addr, instruction));
+        if ends_block {
+            break;
+        }
+        addr += len;
+    }
+    (chain, None)
+}
+
+// `ADD #addr, #0 -> x` and `MUL #addr, #1 -> x` store a constant, which in
+// compiled Intcode is almost always a return address
+fn stored_code_address(instruction: &Instruction) -> Option<i64> {
+    let p = &instruction.params;
+    let identity = match instruction.opcode {
+        Opcode::Add => 0,
+        Opcode::Mul => 1,
+        _ => return None,
+    };
+    if p[0].mode != Mode::Immediate || p[1].mode != Mode::Immediate {
+        return None;
+    }
+    let value = if p[1].value == identity {
+        p[0].value
+    } else if p[0].value == identity {
+        p[1].value
+    } else {
+        return None;
+    };
+    if value > 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Plain text listing, one line per instruction or data run.
+pub fn to_text(lines: &[Line]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        writeln!(out, "{}", line).unwrap();
+    }
+    out
+}
+
+/// Machine readable listing as JSON Lines, one object per line.
+pub fn to_json(lines: &[Line]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let words: Vec<String> = line.words().iter().map(|w| w.to_string()).collect();
+        write!(
+            out,
+            "{{\"addr\":{},\"words\":[{}],",
+            line.addr(),
+            words.join(",")
+        )
+        .unwrap();
+        match line {
+            Line::Code { instruction, .. } => {
+                let operands: Vec<String> = instruction
+                    .params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        format!(
+                            "{{\"mode\":\"{}\",\"value\":{},\"write\":{}}}",
+                            p.mode.name(),
+                            p.value,
+                            instruction.opcode.writes() && i == instruction.params.len() - 1
+                        )
+                    })
+                    .collect();
+                write!(
+                    out,
+                    "\"kind\":\"code\",\"mnemonic\":\"{}\",\"opcode\":{},\"operands\":[{}],",
+                    instruction.opcode.mnemonic(),
+                    instruction.opcode.code(),
+                    operands.join(",")
+                )
+                .unwrap();
+            }
+            Line::Data { .. } => out.push_str("\"kind\":\"data\","),
+            Line::Unknown { error, .. } => write!(
+                out,
+                "\"kind\":\"unknown\",\"error\":{},",
+                json::string(&error.to_string())
+            )
+            .unwrap(),
+        }
+        writeln!(out, "\"text\":{}}}", json::string(&line.to_string())).unwrap();
+    }
+    out
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+
+/// What went wrong while executing an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The instruction's opcode is not one of 1-9 or 99.
+    UnknownOpcode,
+    /// Parameter `param` (zero based) has a mode other than 0, 1 or 2, or
+    /// immediate mode was used for a parameter that is written to.
+    InvalidMode { param: usize, mode: i64 },
+    /// An address (after applying the relative base) was negative.
+    NegativeAddress(i64),
+    /// An address was past the configured memory limit.
+    AddressOutOfRange(i64),
+    /// An addition, multiplication or relative base adjustment overflowed
+    /// under `Arithmetic::Checked`, or a value didn't fit where a big integer
+    /// VM needed a machine word.
+    Overflow,
+    /// A write to `addr`, which is part of the already executed instruction
+    /// at `instruction`, under `OnModify::Trap`.
+    SelfModifyingCode { addr: usize, instruction: usize },
+}
+
+/// An instruction that could not be executed.
+///
+/// The VM is left pointing at the faulting instruction, so it can be
+/// inspected after the error is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmError {
+    pub kind: ErrorKind,
+    /// Address of the faulting instruction.
+    pub pc: usize,
+    /// Raw value of the faulting instruction, including its mode digits.
+    pub opcode: i64,
+    pub relative_base: i64,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::UnknownOpcode => write!(f, "unknown opcode {}", self.opcode % 100)?,
+            ErrorKind::InvalidMode { param, mode } => {
+                write!(f, "invalid mode {} for parameter {}", mode, param + 1)?
+            }
+            ErrorKind::NegativeAddress(addr) => write!(f, "negative address {}", addr)?,
+            ErrorKind::AddressOutOfRange(addr) => write!(f, "address {} out of range", addr)?,
+            ErrorKind::Overflow => write!(f, "arithmetic overflow")?,
+            ErrorKind::SelfModifyingCode { addr, instruction } => write!(
+                f,
+                "write to address {} of the executed instruction at {}",
+                addr, instruction
+            )?,
+        }
+        write!(
+            f,
+            " (pc {}, instruction {}, relative base {})",
+            self.pc, self.opcode, self.relative_base
+        )
+    }
+}
+
+impl Error for VmError {}
@@ -0,0 +1,246 @@
+//! Running VMs as async tasks connected by channels.
+//!
+//! `VM::run_async` reads input by awaiting a `Receiver` and sends outputs to
+//! a `Sender`, so wiring machines together is a matter of handing them the
+//! right channel ends. The `Executor` here is deliberately small: it runs
+//! tasks on the current thread, in the order they become ready, until all of
+//! them finish or every one left is waiting for something nobody will send.
+//!
+//! ```no_run
+//! use intcode::exec::{channel, Executor};
+//! use intcode::VM;
+//!
+//! let program = intcode::read_program("7.txt").unwrap();
+//! let (to_a, a_in) = channel();
+//! let (to_b, b_in) = channel();
+//! let (to_main, results) = channel();
+//! to_a.send(3);
+//! to_b.send(4);
+//! to_a.send(0);
+//!
+//! let (mut a, mut b) = (VM::new(program.clone()), VM::new(program));
+//! let mut executor = Executor::new();
+//! executor.spawn(a.run_async(&a_in, &to_b));
+//! executor.spawn(b.run_async(&b_in, &to_main));
+//! for result in executor.run() {
+//!     result.expect("amplifier still waiting").unwrap();
+//! }
+//! println!("{:?}", results.try_recv());
+//! ```
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::io::{InputSource, OutputSink};
+
+/// Creates a channel with one sender. Clone the sender for more.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(Channel {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+    }));
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+struct Channel<T> {
+    queue: VecDeque<T>,
+    // The task waiting in `Receiver::recv`, if any
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+/// Sending half of a channel. Sending never blocks.
+pub struct Sender<T>(Arc<Mutex<Channel<T>>>);
+
+/// Receiving half of a channel.
+pub struct Receiver<T>(Arc<Mutex<Channel<T>>>);
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        let mut channel = self.0.lock().unwrap();
+        channel.queue.push_back(value);
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.0.lock().unwrap().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+/// Once the last sender is gone, a waiting receiver wakes up to find the
+/// channel closed.
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.0.lock().unwrap();
+        channel.senders -= 1;
+        if channel.senders == 0 {
+            if let Some(waker) = channel.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Resolves to `None` once the channel is empty
+    /// and every sender has been dropped.
+    pub fn recv(&self) -> Recv<'_, T> {
+        Recv(self)
+    }
+
+    /// The next value, if one has already been sent.
+    pub fn try_recv(&self) -> Option<T> {
+        self.0.lock().unwrap().queue.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().queue.is_empty()
+    }
+}
+
+/// Future returned by `Receiver::recv`.
+pub struct Recv<'a, T>(&'a Receiver<T>);
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut channel = (self.0).0.lock().unwrap();
+        if let Some(value) = channel.queue.pop_front() {
+            Poll::Ready(Some(value))
+        } else if channel.senders == 0 {
+            Poll::Ready(None)
+        } else {
+            channel.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Lets a VM read from the channel without awaiting; an empty channel stops
+/// it with `StopReason::NeedsInput`.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.try_recv()
+    }
+}
+
+impl OutputSink for Sender<i64> {
+    fn send_output(&mut self, value: i64) {
+        self.send(value);
+    }
+}
+
+/// Gives the other tasks a turn before continuing.
+pub fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/// Future returned by `yield_now`.
+pub struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+type Task<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Runs futures on the current thread and collects what they resolve to.
+/// Tasks may borrow from the caller, so channels and VMs can live on the
+/// stack of whoever runs the executor.
+pub struct Executor<'a, T = ()> {
+    // Finished tasks leave an empty slot so ids stay valid
+    tasks: Vec<Option<Task<'a, T>>>,
+    results: Vec<Option<T>>,
+    ready: Arc<Ready>,
+}
+
+// Ids of tasks that were woken and should be polled again
+#[derive(Default)]
+struct Ready(Mutex<VecDeque<usize>>);
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Ready>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut ready = self.ready.0.lock().unwrap();
+        if !ready.contains(&self.id) {
+            ready.push_back(self.id);
+        }
+    }
+}
+
+impl<'a, T> Executor<'a, T> {
+    pub fn new() -> Executor<'a, T> {
+        Executor {
+            tasks: Vec::new(),
+            results: Vec::new(),
+            ready: Arc::default(),
+        }
+    }
+
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + 'a,
+    {
+        self.ready.0.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(future)));
+        self.results.push(None);
+    }
+
+    /// Polls tasks until they have all finished or none of them can make
+    /// progress. Returns what each task resolved to, in the order they were
+    /// spawned, with `None` for tasks left waiting.
+    pub fn run(mut self) -> Vec<Option<T>> {
+        loop {
+            let id = match self.ready.0.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            let task = match self.tasks[id].as_mut() {
+                Some(task) => task,
+                None => continue,
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                id,
+                ready: self.ready.clone(),
+            }));
+            if let Poll::Ready(result) = task.as_mut().poll(&mut Context::from_waker(&waker)) {
+                self.tasks[id] = None;
+                self.results[id] = Some(result);
+            }
+        }
+        self.results
+    }
+}
+
+impl<T> Default for Executor<'_, T> {
+    fn default() -> Self {
+        Executor::new()
+    }
+}
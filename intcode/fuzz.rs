@@ -0,0 +1,602 @@
+//! Differential fuzzing of the ways a VM can execute a program.
+//!
+//! Random programs are run on every `Variant`: a plain reference interpreter
+//! that shares no code with `VM`, both engines, single stepping, tracing,
+//! forking, snapshot round trips and code mapping. They must all agree on
+//! how the program stopped, what it output and the memory it left behind.
+//! Programs that never overflow are also run with checked and saturating
+//! arithmetic and on `BigVm`, which only differ from wrapping when they do.
+//! A program that makes any of them disagree is shrunk by `minimize` to
+//! something small enough to debug by hand.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::bigint::{BigInt, BigVm};
+use crate::codemap::{CodeMap, OnModify};
+use crate::error::{ErrorKind, VmError};
+use crate::instruction::{Mode, Opcode, OPCODES};
+use crate::snapshot::Snapshot;
+use crate::trace::Tracer;
+use crate::vm::{Arithmetic, Budget, Engine, StopReason, VM};
+
+/// Small, seedable xorshift generator, so a failing run can be repeated from
+/// its seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `low..high`.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next_u64() % (high - low) as u64) as i64
+    }
+
+    /// True with a chance of one in `n`.
+    pub fn one_in(&mut self, n: u64) -> bool {
+        self.next_u64().is_multiple_of(n)
+    }
+}
+
+/// Generates a program of about `len` words made of well formed
+/// instructions. Operands mostly point back into the program, so programs
+/// read and overwrite their own code, jump around in it and sometimes land
+/// in the middle of an instruction.
+pub fn generate(rng: &mut Rng, len: usize) -> Vec<i64> {
+    let mut program = Vec::with_capacity(len + 4);
+    let span = len as i64 + 8;
+    while program.len() < len {
+        let op = if rng.one_in(24) {
+            Opcode::Halt
+        } else {
+            // Every opcode but the last, which is halt
+            OPCODES[rng.range(0, OPCODES.len() as i64 - 1) as usize]
+        };
+        let n = op.num_params();
+        let mut code = op.code();
+        let mut args = Vec::with_capacity(n);
+        for i in 0..n {
+            let write = op.writes() && i == n - 1;
+            let mode = match rng.range(0, if write { 2 } else { 3 }) {
+                0 => Mode::Position,
+                1 if !write => Mode::Immediate,
+                _ => Mode::Relative,
+            };
+            code += mode.digit() * [100, 1_000, 10_000][i];
+            args.push(match (mode, op) {
+                (Mode::Immediate, Opcode::JumpIfTrue) | (Mode::Immediate, Opcode::JumpIfFalse)
+                    if i == 1 =>
+                {
+                    rng.range(0, span)
+                }
+                (Mode::Immediate, Opcode::AdjustBase) => rng.range(-8, 9),
+                (Mode::Immediate, _) => rng.range(-4, span),
+                (Mode::Position, _) => rng.range(0, span),
+                (Mode::Relative, _) => rng.range(-8, span),
+            });
+        }
+        program.push(code);
+        program.extend(args);
+    }
+    program
+}
+
+/// How a run ended, in enough detail to tell any two executions apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub stop: Result<StopReason, VmError>,
+    pub outputs: Vec<i64>,
+    pub pc: usize,
+    pub relative_base: i64,
+    pub executed: u64,
+    /// Every non-zero word of memory as (address, value), lowest first.
+    pub memory: Vec<(usize, i64)>,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.stop {
+            Ok(stop) => write!(f, "{:?}", stop)?,
+            Err(e) => write!(f, "error: {}", e)?,
+        }
+        write!(
+            f,
+            " after {} instructions at pc {}, rb {}, outputs {:?}",
+            self.executed, self.pc, self.relative_base, self.outputs
+        )
+    }
+}
+
+/// One way of running a program. Every variant must produce the same
+/// `Outcome` for the same program, input and step limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// A small interpreter written separately from `VM`, which every other
+    /// variant is checked against.
+    Reference,
+    /// The VM's interpreter, decoding every instruction as it runs.
+    Interpreter,
+    /// The default engine, running from the decoded instruction cache.
+    Cached,
+    /// The cached engine resumed one instruction at a time.
+    Stepped,
+    /// The interpreter with a tracer attached.
+    Traced,
+    /// Forked every few instructions, keeping the parents alive so that
+    /// their shared pages have to be copied.
+    Forked,
+    /// Saved to a snapshot and restored into a fresh VM every few
+    /// instructions.
+    Snapshots,
+    /// The cached engine with a code map recording every write to executed
+    /// code.
+    Mapped,
+    /// The cached engine faulting on overflow. Like the two below, only run
+    /// on programs that never overflow.
+    Checked,
+    /// The cached engine clamping on overflow.
+    Saturating,
+    /// `BigVm`, whose words never overflow.
+    Big,
+}
+
+pub const VARIANTS: [Variant; 11] = [
+    Variant::Reference,
+    Variant::Interpreter,
+    Variant::Cached,
+    Variant::Stepped,
+    Variant::Traced,
+    Variant::Forked,
+    Variant::Snapshots,
+    Variant::Mapped,
+    Variant::Checked,
+    Variant::Saturating,
+    Variant::Big,
+];
+
+// Instructions between forks and snapshots
+const SLICE: u64 = 7;
+
+impl Variant {
+    /// Whether the variant only agrees with the others on programs that
+    /// never overflow.
+    pub fn needs_no_overflow(self) -> bool {
+        matches!(self, Variant::Checked | Variant::Saturating | Variant::Big)
+    }
+
+    /// Runs `program` with `input` queued until it halts, faults, runs out of
+    /// input or has executed `max_steps` instructions.
+    pub fn run(self, program: &[i64], input: &[i64], max_steps: u64) -> Outcome {
+        match self {
+            Variant::Reference => return Reference::new(program, input).run(max_steps),
+            Variant::Big => return run_big(program, input, max_steps),
+            _ => {}
+        }
+        let mut vm = VM::new(program.to_vec());
+        for v in input {
+            vm.push_input(*v);
+        }
+        match self {
+            Variant::Interpreter => vm.set_engine(Engine::Interpreter),
+            Variant::Reference
+            | Variant::Cached
+            | Variant::Stepped
+            | Variant::Forked
+            | Variant::Snapshots
+            | Variant::Big => {}
+            Variant::Checked => vm.set_arithmetic(Arithmetic::Checked),
+            Variant::Saturating => vm.set_arithmetic(Arithmetic::Saturating),
+            Variant::Mapped => vm.attach_code_map(CodeMap::new(OnModify::Record)),
+            Variant::Traced => {
+                vm.set_engine(Engine::Interpreter);
+                vm.attach_tracer(Tracer::new(io::sink()));
+            }
+        }
+        let slice = match self {
+            Variant::Stepped => 1,
+            Variant::Forked | Variant::Snapshots => SLICE,
+            _ => max_steps,
+        };
+
+        let mut outputs = Vec::new();
+        let mut executed = 0;
+        let mut parents = Vec::new();
+        let stop = loop {
+            if executed >= max_steps {
+                break Ok(StopReason::BudgetExhausted);
+            }
+            let before = vm.instructions_executed();
+            let stop = vm.resume_within(Budget::steps(slice.min(max_steps - executed)));
+            executed += vm.instructions_executed() - before;
+            match stop {
+                Ok(StopReason::Output(v)) => outputs.push(v),
+                Ok(StopReason::BudgetExhausted) => match self {
+                    Variant::Forked => {
+                        let child = vm.fork();
+                        parents.push(vm);
+                        vm = child;
+                    }
+                    Variant::Snapshots => {
+                        let text = vm.snapshot().to_string();
+                        vm = VM::new(Vec::new());
+                        vm.restore(&Snapshot::parse(&text).unwrap());
+                    }
+                    _ => {}
+                },
+                stop => break stop,
+            }
+        };
+
+        let memory = vm
+            .memory()
+            .pages()
+            .into_iter()
+            .flat_map(|(start, words)| {
+                words
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, w)| **w != 0)
+                    .map(move |(i, w)| (start + i, *w))
+            })
+            .collect();
+        Outcome {
+            stop,
+            outputs,
+            pc: vm.pc(),
+            relative_base: vm.relative_base(),
+            executed,
+            memory,
+        }
+    }
+}
+
+// Runs on `BigVm` one instruction at a time, to count them and stop after
+// `max_steps`
+fn run_big(program: &[i64], input: &[i64], max_steps: u64) -> Outcome {
+    let mut vm = BigVm::new(program.iter().map(|v| BigInt::from(*v)).collect());
+    for v in input {
+        vm.push_input(*v);
+    }
+    let mut outputs = Vec::new();
+    let mut executed = 0;
+    let stop = loop {
+        if executed >= max_steps {
+            break Ok(StopReason::BudgetExhausted);
+        }
+        match vm.resume_for(1) {
+            Ok(StopReason::BudgetExhausted) => executed += 1,
+            Ok(StopReason::Output(v)) => {
+                executed += 1;
+                outputs.push(v);
+            }
+            Ok(StopReason::Halted) => {
+                executed += 1;
+                break Ok(StopReason::Halted);
+            }
+            stop => break stop,
+        }
+    };
+    let memory = vm
+        .nonzero_words()
+        .into_iter()
+        // Without overflow every word fits
+        .map(|(addr, v)| (addr, v.to_i64().unwrap_or(i64::MAX)))
+        .collect();
+    Outcome {
+        stop,
+        outputs,
+        pc: vm.pc(),
+        relative_base: vm.relative_base(),
+        executed,
+        memory,
+    }
+}
+
+// Runs programs as the puzzles describe them, with wrapping arithmetic and
+// without any of the VM's machinery, so a bug in `VM` can't hide by being
+// shared by every variant
+struct Reference {
+    memory: BTreeMap<usize, i64>,
+    input: Vec<i64>,
+    pc: usize,
+    relative_base: i64,
+    // Set once any sum, product or relative address has wrapped
+    overflowed: bool,
+}
+
+impl Reference {
+    fn new(program: &[i64], input: &[i64]) -> Reference {
+        Reference {
+            memory: program.iter().copied().enumerate().collect(),
+            // Reversed, so the next input can be popped
+            input: input.iter().rev().copied().collect(),
+            pc: 0,
+            relative_base: 0,
+            overflowed: false,
+        }
+    }
+
+    fn run(&mut self, max_steps: u64) -> Outcome {
+        let mut outputs = Vec::new();
+        let mut executed = 0;
+        let stop = loop {
+            if executed >= max_steps {
+                break Ok(StopReason::BudgetExhausted);
+            }
+            let pc = self.pc;
+            let code = self.fetch();
+            match self.step(code) {
+                Ok(None) => executed += 1,
+                Ok(Some(StopReason::NeedsInput)) => {
+                    self.pc = pc;
+                    break Ok(StopReason::NeedsInput);
+                }
+                Ok(Some(StopReason::Output(v))) => {
+                    executed += 1;
+                    outputs.push(v);
+                }
+                Ok(Some(stop)) => {
+                    executed += 1;
+                    break Ok(stop);
+                }
+                Err(kind) => {
+                    self.pc = pc;
+                    break Err(VmError {
+                        kind,
+                        pc,
+                        opcode: code,
+                        relative_base: self.relative_base,
+                    });
+                }
+            }
+        };
+        Outcome {
+            stop,
+            outputs,
+            pc: self.pc,
+            relative_base: self.relative_base,
+            executed,
+            memory: self
+                .memory
+                .iter()
+                .filter(|(_, v)| **v != 0)
+                .map(|(a, v)| (*a, *v))
+                .collect(),
+        }
+    }
+
+    fn fetch(&mut self) -> i64 {
+        let v = self.memory.get(&self.pc).copied().unwrap_or(0);
+        self.pc += 1;
+        v
+    }
+
+    fn address(addr: i64) -> Result<usize, ErrorKind> {
+        if addr < 0 {
+            Err(ErrorKind::NegativeAddress(addr))
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    fn mode(code: i64, i: usize) -> i64 {
+        code / [100, 1_000, 10_000][i] % 10
+    }
+
+    fn wrapping(&mut self, value: Option<i64>, wrapped: i64) -> i64 {
+        self.overflowed |= value.is_none();
+        wrapped
+    }
+
+    fn add(&mut self, a: i64, b: i64) -> i64 {
+        self.wrapping(a.checked_add(b), a.wrapping_add(b))
+    }
+
+    // Reads parameter `i` of the instruction `code`
+    fn load(&mut self, code: i64, i: usize) -> Result<i64, ErrorKind> {
+        let p = self.fetch();
+        let addr = match Reference::mode(code, i) {
+            0 => p,
+            1 => return Ok(p),
+            2 => self.add(p, self.relative_base),
+            mode => return Err(ErrorKind::InvalidMode { param: i, mode }),
+        };
+        let addr = Reference::address(addr)?;
+        Ok(self.memory.get(&addr).copied().unwrap_or(0))
+    }
+
+    // The address parameter `i` of the instruction `code` writes to
+    fn store_at(&mut self, code: i64, i: usize) -> Result<usize, ErrorKind> {
+        let p = self.fetch();
+        match Reference::mode(code, i) {
+            0 => Reference::address(p),
+            2 => Reference::address(self.add(p, self.relative_base)),
+            mode => Err(ErrorKind::InvalidMode { param: i, mode }),
+        }
+    }
+
+    fn step(&mut self, code: i64) -> Result<Option<StopReason>, ErrorKind> {
+        match code % 100 {
+            op @ (1 | 2 | 7 | 8) => {
+                let a = self.load(code, 0)?;
+                let b = self.load(code, 1)?;
+                let to = self.store_at(code, 2)?;
+                let value = match op {
+                    1 => self.add(a, b),
+                    2 => self.wrapping(a.checked_mul(b), a.wrapping_mul(b)),
+                    7 => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                self.memory.insert(to, value);
+            }
+            3 => {
+                let to = self.store_at(code, 0)?;
+                match self.input.pop() {
+                    Some(v) => {
+                        self.memory.insert(to, v);
+                    }
+                    None => return Ok(Some(StopReason::NeedsInput)),
+                }
+            }
+            4 => return Ok(Some(StopReason::Output(self.load(code, 0)?))),
+            op @ (5 | 6) => {
+                let value = self.load(code, 0)?;
+                let target = self.load(code, 1)?;
+                if (value != 0) == (op == 5) {
+                    self.pc = Reference::address(target)?;
+                }
+            }
+            9 => {
+                let delta = self.load(code, 0)?;
+                self.relative_base = self.add(self.relative_base, delta);
+            }
+            99 => return Ok(Some(StopReason::Halted)),
+            _ => return Err(ErrorKind::UnknownOpcode),
+        }
+        Ok(None)
+    }
+}
+
+/// Variants that disagreed with `Variant::Reference` on a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    pub max_steps: u64,
+    pub expected: Outcome,
+    pub mismatches: Vec<(Variant, Outcome)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |values: &[i64]| {
+            values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+        };
+        writeln!(f, "program: {}", join(&self.program))?;
+        writeln!(f, "input: {}", join(&self.input))?;
+        writeln!(f, "max steps: {}", self.max_steps)?;
+        writeln!(f, "{:?}: {}", Variant::Reference, self.expected)?;
+        for (variant, outcome) in &self.mismatches {
+            writeln!(f, "{:?}: {}", variant, outcome)?;
+            if outcome.memory != self.expected.memory {
+                writeln!(f, "  memory differs")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the program on every variant and reports the ones that disagree
+/// with the reference interpreter. Variants that need no overflow are left
+/// out if the reference run overflowed.
+pub fn check(program: &[i64], input: &[i64], max_steps: u64) -> Option<Divergence> {
+    let mut reference = Reference::new(program, input);
+    let expected = reference.run(max_steps);
+    let mismatches: Vec<(Variant, Outcome)> = VARIANTS[1..]
+        .iter()
+        .filter(|v| !(reference.overflowed && v.needs_no_overflow()))
+        .map(|v| (*v, v.run(program, input, max_steps)))
+        .filter(|(_, outcome)| *outcome != expected)
+        .collect();
+    if mismatches.is_empty() {
+        None
+    } else {
+        Some(Divergence {
+            program: program.to_vec(),
+            input: input.to_vec(),
+            max_steps,
+            expected,
+            mismatches,
+        })
+    }
+}
+
+/// Shrinks a divergence: drops runs of words and inputs, then simplifies the
+/// words that are left, for as long as the variants still disagree.
+pub fn minimize(divergence: &Divergence) -> Divergence {
+    let mut best = divergence.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for shrink_input in [false, true] {
+            let mut chunk = if shrink_input {
+                best.input.len()
+            } else {
+                best.program.len()
+            } / 2;
+            while chunk > 0 {
+                let mut start = 0;
+                loop {
+                    let words = if shrink_input { &best.input } else { &best.program };
+                    if start + chunk > words.len() {
+                        break;
+                    }
+                    let mut shorter = words.clone();
+                    shorter.drain(start..start + chunk);
+                    let found = if shrink_input {
+                        check(&best.program, &shorter, best.max_steps)
+                    } else {
+                        check(&shorter, &best.input, best.max_steps)
+                    };
+                    match found {
+                        Some(d) => {
+                            best = d;
+                            changed = true;
+                        }
+                        None => start += chunk,
+                    }
+                }
+                chunk /= 2;
+            }
+        }
+        for i in 0..best.program.len() {
+            for simpler in [0, 1, best.program[i] / 2] {
+                if best.program[i] == simpler {
+                    continue;
+                }
+                let mut program = best.program.clone();
+                program[i] = simpler;
+                if let Some(d) = check(&program, &best.input, best.max_steps) {
+                    best = d;
+                    changed = true;
+                    break;
+                }
+            }
+        }
+    }
+    // The fewest steps that still show the difference
+    while best.max_steps > 1 {
+        match check(&best.program, &best.input, best.max_steps - 1) {
+            Some(d) => best = d,
+            None => break,
+        }
+    }
+    best
+}
+
+/// Checks `runs` random programs and inputs generated from `seed`, and
+/// returns the first divergence found, minimized.
+pub fn fuzz(seed: u64, runs: u64, max_steps: u64) -> Option<Divergence> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..runs {
+        let len = rng.range(4, 80) as usize;
+        let program = generate(&mut rng, len);
+        let input: Vec<i64> = (0..rng.range(0, 8)).map(|_| rng.range(-20, 100)).collect();
+        if let Some(divergence) = check(&program, &input, max_steps) {
+            return Some(minimize(&divergence));
+        }
+    }
+    None
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Add,
+    Mul,
+    Input,
+    Output,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustBase,
+    Halt,
+}
+
+pub const OPCODES: [Opcode; 10] = [
+    Opcode::Add,
+    Opcode::Mul,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::JumpIfTrue,
+    Opcode::JumpIfFalse,
+    Opcode::LessThan,
+    Opcode::Equals,
+    Opcode::AdjustBase,
+    Opcode::Halt,
+];
+
+impl Opcode {
+    /// Decodes the low two digits of an instruction.
+    pub fn from_code(code: i64) -> Option<Opcode> {
+        match code {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Mul),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::AdjustBase),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn from_mnemonic(s: &str) -> Option<Opcode> {
+        OPCODES
+            .iter()
+            .find(|op| op.mnemonic().eq_ignore_ascii_case(s))
+            .copied()
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustBase => 9,
+            Opcode::Halt => 99,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::Input => "IN",
+            Opcode::Output => "OUT",
+            Opcode::JumpIfTrue => "JNZ",
+            Opcode::JumpIfFalse => "JZ",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::AdjustBase => "ARB",
+            Opcode::Halt => "HLT",
+        }
+    }
+
+    /// Number of parameters, including the one written to.
+    pub fn num_params(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::AdjustBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// Whether the last parameter is an address that gets written to.
+    pub fn writes(self) -> bool {
+        matches!(
+            self,
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals | Opcode::Input
+        )
+    }
+
+    pub fn is_jump(self) -> bool {
+        self == Opcode::JumpIfTrue || self == Opcode::JumpIfFalse
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn from_digit(d: i64) -> Option<Mode> {
+        match d {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Position => "position",
+            Mode::Immediate => "immediate",
+            Mode::Relative => "relative",
+        }
+    }
+}
+
+// Divisor that shifts each parameter's mode digit down to the ones place
+const MODE_DIVISORS: [i64; 4] = [100, 1_000, 10_000, 100_000];
+
+/// Mode digit of parameter `param` (zero based) of a raw instruction.
+pub fn mode_digit(code: i64, param: usize) -> i64 {
+    (code / MODE_DIVISORS[param]) % 10
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+            Mode::Relative => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+/// Why a word couldn't be decoded as an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(i64),
+    InvalidMode { param: usize, mode: i64 },
+    /// The instruction runs past the end of the program.
+    Truncated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            DecodeError::InvalidMode { param, mode } => {
+                write!(f, "invalid mode {} for parameter {}", mode, param + 1)
+            }
+            DecodeError::Truncated => write!(f, "truncated instruction"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub params: Vec<Param>,
+}
+
+impl Instruction {
+    /// Decodes the instruction starting at `addr`.
+    ///
+    /// Mode digits past the instruction's parameter count must be zero and
+    /// written parameters can't be immediate, so stray data is less likely to
+    /// decode as code.
+    pub fn decode(program: &[i64], addr: usize) -> Result<Instruction, DecodeError> {
+        let code = *program.get(addr).ok_or(DecodeError::Truncated)?;
+        if code < 0 {
+            return Err(DecodeError::UnknownOpcode(code));
+        }
+        let opcode = Opcode::from_code(code % 100).ok_or(DecodeError::UnknownOpcode(code % 100))?;
+        let n = opcode.num_params();
+        if code / MODE_DIVISORS[n] != 0 {
+            return Err(DecodeError::InvalidMode {
+                param: n,
+                mode: mode_digit(code, n),
+            });
+        }
+        let mut params = Vec::with_capacity(n);
+        for i in 0..n {
+            let digit = mode_digit(code, i);
+            let mode = match Mode::from_digit(digit) {
+                Some(Mode::Immediate) if opcode.writes() && i == n - 1 => None,
+                m => m,
+            }
+            .ok_or(DecodeError::InvalidMode {
+                param: i,
+                mode: digit,
+            })?;
+            let value = *program.get(addr + 1 + i).ok_or(DecodeError::Truncated)?;
+            params.push(Param { mode, value });
+        }
+        Ok(Instruction { opcode, params })
+    }
+
+    /// Number of words the instruction occupies.
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    /// Encodes the instruction back into memory words.
+    pub fn encode(&self) -> Vec<i64> {
+        let mut code = self.opcode.code();
+        for (i, p) in self.params.iter().enumerate() {
+            code += p.mode.digit() * MODE_DIVISORS[i];
+        }
+        let mut words = vec![code];
+        words.extend(self.params.iter().map(|p| p.value));
+        words
+    }
+
+    /// Target of a jump whose destination is known statically.
+    pub fn jump_target(&self) -> Option<i64> {
+        if self.opcode.is_jump() && self.params[1].mode == Mode::Immediate {
+            Some(self.params[1].value)
+        } else {
+            None
+        }
+    }
+
+    /// Whether execution can never continue with the next instruction.
+    pub fn ends_block(&self) -> bool {
+        match self.opcode {
+            Opcode::Halt => true,
+            Opcode::JumpIfTrue => self.params[0].mode == Mode::Immediate && self.params[0].value != 0,
+            Opcode::JumpIfFalse => {
+                self.params[0].mode == Mode::Immediate && self.params[0].value == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Formats as e.g. `ADD [rb+3], #7 -> [120]`: position operands in
+/// brackets, immediates with `#`, relative operands as `[rb+N]` and the
+/// written operand after `->`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        let (reads, write) = if self.opcode.writes() {
+            let (last, rest) = self.params.split_last().unwrap();
+            (rest, Some(last))
+        } else {
+            (&self.params[..], None)
+        };
+        for (i, p) in reads.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, p)?;
+        }
+        if let Some(p) = write {
+            write!(f, " -> {}", p)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Where an input instruction gets its value from once the VM's own input
+/// queue is empty.
+pub trait InputSource: Send {
+    /// Returns the next input, or `None` if there is nothing to read right
+    /// now. The VM then stops with `StopReason::NeedsInput`.
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+/// Where output instructions send their values. While a sink is attached the
+/// VM keeps running through outputs instead of stopping on each one.
+pub trait OutputSink: Send {
+    fn send_output(&mut self, value: i64);
+}
+
+impl InputSource for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for VecDeque<i64> {
+    fn send_output(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+/// A FIFO queue that can be shared between VMs, e.g. to feed one machine's
+/// output into another's input.
+#[derive(Debug, Clone, Default)]
+pub struct Queue(Arc<Mutex<VecDeque<i64>>>);
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue::default()
+    }
+
+    pub fn push(&self, value: i64) {
+        self.0.lock().unwrap().push_back(value);
+    }
+
+    pub fn pop(&self) -> Option<i64> {
+        self.0.lock().unwrap().pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl InputSource for Queue {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop()
+    }
+}
+
+impl OutputSink for Queue {
+    fn send_output(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// Input produced by a closure. See `input_fn`.
+pub struct FnInput<F>(F);
+
+/// Output handled by a closure. See `output_fn`.
+pub struct FnOutput<F>(F);
+
+/// Calls `f` whenever the program reads input.
+pub fn input_fn<F>(f: F) -> FnInput<F>
+where
+    F: FnMut() -> Option<i64> + Send,
+{
+    FnInput(f)
+}
+
+/// Calls `f` with every value the program outputs.
+pub fn output_fn<F>(f: F) -> FnOutput<F>
+where
+    F: FnMut(i64) + Send,
+{
+    FnOutput(f)
+}
+
+impl<F> InputSource for FnInput<F>
+where
+    F: FnMut() -> Option<i64> + Send,
+{
+    fn next_input(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+impl<F> OutputSink for FnOutput<F>
+where
+    F: FnMut(i64) + Send,
+{
+    fn send_output(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Feeds lines of text to an ASCII program one character at a time, reading
+/// a new line from `reader` only when the previous one has been consumed.
+pub struct AsciiInput<R> {
+    reader: R,
+    line: VecDeque<i64>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> AsciiInput<R> {
+        AsciiInput {
+            reader,
+            line: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead + Send> InputSource for AsciiInput<R> {
+    fn next_input(&mut self) -> Option<i64> {
+        if self.line.is_empty() {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            self.line.extend(line.bytes().filter(|b| *b != b'\r').map(i64::from));
+        }
+        self.line.pop_front()
+    }
+}
+
+/// Blocks until another thread sends a value. Reports no input once every
+/// sender has hung up.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver hangs up are dropped.
+impl OutputSink for Sender<i64> {
+    fn send_output(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+/// Captures every output. Clones share the same recording, so keep one
+/// around to read back what the VM produced.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder(Arc<Mutex<Vec<i64>>>);
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    pub fn values(&self) -> Vec<i64> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Recorded values decoded as ASCII.
+    pub fn text(&self) -> String {
+        self.0.lock().unwrap().iter().map(|x| *x as u8 as char).collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl OutputSink for Recorder {
+    fn send_output(&mut self, value: i64) {
+        self.0.lock().unwrap().push(value);
+    }
+}
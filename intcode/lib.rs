@@ -0,0 +1,64 @@
+//! Shared Intcode interpreter used by every Intcode day.
+//!
+//! Build the library with `make intcode`, then compile a day binary against it
+//! with `rustc -O -L ../target dayN.rs`.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub mod amplifier;
+pub mod asm;
+pub mod bigint;
+mod cache;
+pub mod cfg;
+pub mod circuit;
+pub mod codemap;
+pub mod debug;
+pub mod decompile;
+pub mod disasm;
+mod error;
+pub mod exec;
+pub mod fuzz;
+mod instruction;
+pub mod io;
+mod json;
+mod memory;
+pub mod profile;
+pub mod search;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+mod vm;
+
+pub use crate::error::{ErrorKind, VmError};
+pub use crate::instruction::{DecodeError, Instruction, Mode, Opcode, Param};
+pub use crate::io::{InputSource, OutputSink};
+pub use crate::memory::Memory;
+pub use crate::profile::Profiler;
+pub use crate::snapshot::{Snapshot, SnapshotError};
+pub use crate::trace::Tracer;
+pub use crate::vm::{Arithmetic, Budget, Engine, StopReason, VM};
+
+/// Reads a comma separated Intcode program from `filename`.
+pub fn read_program<P>(filename: P) -> std::io::Result<Vec<i64>>
+where
+    P: AsRef<Path>,
+{
+    let mut file = File::open(filename)?;
+    let mut out = String::new();
+    file.read_to_string(&mut out)?;
+    parse_program(&out)
+}
+
+/// Parses a comma separated Intcode program.
+pub fn parse_program(source: &str) -> std::io::Result<Vec<i64>> {
+    source
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
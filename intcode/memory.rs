@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+// Pages below this index live in a flat table, anything above is sparse
+const DENSE_PAGES: usize = 1 << 12;
+
+type Words = [i64; PAGE_SIZE];
+
+// A page is shared until it's first written to, and from then on owned by
+// the memory that wrote it, so writes don't have to check for other owners
+#[derive(Debug)]
+enum Page {
+    Owned(Box<Words>),
+    Shared(Arc<Words>),
+}
+
+impl Page {
+    fn words(&self) -> &Words {
+        match self {
+            Page::Owned(words) => words,
+            Page::Shared(words) => words,
+        }
+    }
+
+    // Hands an owned page over to sharing, to be copied again by whichever
+    // side next writes to it
+    fn share(&mut self) {
+        if let Page::Owned(words) = self {
+            *self = Page::Shared(Arc::new(**words));
+        }
+    }
+
+    fn words_mut(&mut self) -> &mut Words {
+        if let Page::Shared(words) = self {
+            *self = Page::Owned(Box::new(**words));
+        }
+        match self {
+            Page::Owned(words) => words,
+            Page::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+impl Clone for Page {
+    fn clone(&self) -> Page {
+        match self {
+            Page::Owned(words) => Page::Owned(words.clone()),
+            Page::Shared(words) => Page::Shared(words.clone()),
+        }
+    }
+}
+
+/// Intcode memory that grows on demand.
+///
+/// Reading an address that was never written returns 0 and writing allocates
+/// the page that holds it. Low addresses are kept in a flat page table, high
+/// ones in a sparse map, so a stray write far past the program doesn't
+/// allocate everything in between.
+///
+/// Pages are copy on write. A page that has only been read is shared with
+/// every clone and copied the first time one of them writes to it. After that
+/// it belongs to the memory that wrote it and is written in place, so
+/// cloning a `Memory` copies the pages it has written to and shares the rest.
+/// `fork` shares those as well.
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    pages: Vec<Option<Page>>,
+    sparse: HashMap<usize, Page>,
+    limit: Option<usize>,
+}
+
+impl Memory {
+    pub fn new(program: &[i64]) -> Memory {
+        let mut memory = Memory::default();
+        for (i, chunk) in program.chunks(PAGE_SIZE).enumerate() {
+            let mut words = [0; PAGE_SIZE];
+            words[..chunk.len()].copy_from_slice(chunk);
+            let page = Page::Shared(Arc::new(words));
+            // Past the flat table, where `page` and `page_mut` look for them
+            if i < DENSE_PAGES {
+                memory.pages.push(Some(page));
+            } else {
+                memory.sparse.insert(i, page);
+            }
+        }
+        memory
+    }
+
+    #[inline]
+    fn page(&self, page: usize) -> Option<&Words> {
+        if page < DENSE_PAGES {
+            self.pages.get(page).and_then(|p| p.as_ref()).map(Page::words)
+        } else {
+            self.sparse.get(&page).map(Page::words)
+        }
+    }
+
+    fn page_mut(&mut self, page: usize) -> &mut Words {
+        let new = || Page::Owned(Box::new([0; PAGE_SIZE]));
+        if page < DENSE_PAGES {
+            if page >= self.pages.len() {
+                self.pages.resize_with(page + 1, || None);
+            }
+            self.pages[page].get_or_insert_with(new).words_mut()
+        } else {
+            self.sparse.entry(page).or_insert_with(new).words_mut()
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, addr: usize) -> i64 {
+        self.page(addr >> PAGE_BITS)
+            .map(|p| p[addr & (PAGE_SIZE - 1)])
+            .unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn set(&mut self, addr: usize, val: i64) {
+        if let Some(Some(page)) = self.pages.get_mut(addr >> PAGE_BITS) {
+            page.words_mut()[addr & (PAGE_SIZE - 1)] = val;
+        } else {
+            self.page_mut(addr >> PAGE_BITS)[addr & (PAGE_SIZE - 1)] = val;
+        }
+    }
+
+    /// The first `len` words, e.g. to disassemble the program as it is now.
+    pub fn to_vec(&self, len: usize) -> Vec<i64> {
+        (0..len).map(|addr| self.get(addr)).collect()
+    }
+
+    /// One past the highest address the program may touch, if capped.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Caps memory so that addresses at or past `limit` fault instead of
+    /// allocating.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Allocated pages as their first address and contents, lowest first.
+    pub(crate) fn pages(&self) -> Vec<(usize, &[i64])> {
+        let mut pages: Vec<(usize, &[i64])> = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| p.as_ref().map(|p| (i << PAGE_BITS, &p.words()[..])))
+            .chain(self.sparse.iter().map(|(i, p)| (i << PAGE_BITS, &p.words()[..])))
+            .collect();
+        pages.sort_by_key(|(addr, _)| *addr);
+        pages
+    }
+
+    /// A copy that shares every page with this memory until either side
+    /// writes to it. Unlike `clone`, which copies the pages this memory has
+    /// written to, nothing is copied for the fork itself.
+    pub fn fork(&mut self) -> Memory {
+        for page in self.pages.iter_mut().flatten().chain(self.sparse.values_mut()) {
+            page.share();
+        }
+        self.clone()
+    }
+
+    /// Number of words currently backed by allocated pages.
+    pub fn allocated(&self) -> usize {
+        (self.pages.iter().filter(|p| p.is_some()).count() + self.sparse.len()) * PAGE_SIZE
+    }
+
+    /// Number of allocated words whose pages are still shared with a clone,
+    /// i.e. that neither side has written to since.
+    pub fn shared(&self) -> usize {
+        let shared = self
+            .pages
+            .iter()
+            .flatten()
+            .chain(self.sparse.values())
+            .filter(|p| matches!(p, Page::Shared(words) if Arc::strong_count(words) > 1))
+            .count();
+        shared * PAGE_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_past_the_flat_table_keep_their_tail() {
+        let end = (DENSE_PAGES + 1) * PAGE_SIZE;
+        let mut program = vec![0; end + 1];
+        program[end - 1] = 7;
+        program[end] = 8;
+        let mut memory = Memory::new(&program);
+        assert_eq!(memory.get(end - 1), 7);
+        assert_eq!(memory.get(end), 8);
+
+        memory.set(end, 9);
+        assert_eq!(memory.get(end - 1), 7);
+        assert_eq!(memory.get(end), 9);
+    }
+}
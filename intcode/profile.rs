@@ -0,0 +1,221 @@
+//! Counts where a program spends its instructions.
+//!
+//! Attach a `Profiler` with `VM::attach_profiler` and it counts every
+//! executed instruction by opcode, by instruction code (opcode plus parameter
+//! modes) and by address. Profiles of several VMs running the same program,
+//! e.g. day19's drones, can be combined with `Profiler::merge`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::instruction::{self, Instruction, Mode, Opcode, OPCODES};
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    total: u64,
+    // Keyed by address, since programs can jump anywhere
+    pcs: HashMap<usize, u64>,
+    // Keyed by the raw instruction, which includes the mode digits
+    codes: HashMap<i64, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub(crate) fn record(&mut self, pc: usize, code: i64) {
+        self.total += 1;
+        *self.pcs.entry(pc).or_insert(0) += 1;
+        *self.codes.entry(code).or_insert(0) += 1;
+    }
+
+    /// Adds the counts from `other`.
+    pub fn merge(&mut self, other: &Profiler) {
+        self.total += other.total;
+        for (pc, count) in &other.pcs {
+            *self.pcs.entry(*pc).or_insert(0) += count;
+        }
+        for (code, count) in &other.codes {
+            *self.codes.entry(*code).or_insert(0) += count;
+        }
+    }
+
+    /// Instructions executed while profiling.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Executions per opcode, most frequent first.
+    pub fn opcodes(&self) -> Vec<(Opcode, u64)> {
+        let mut counts: Vec<(Opcode, u64)> = OPCODES
+            .iter()
+            .map(|op| {
+                let count = self
+                    .codes
+                    .iter()
+                    .filter(|(code, _)| *code % 100 == op.code())
+                    .map(|(_, count)| count)
+                    .sum();
+                (*op, count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        counts.sort_by_key(|(op, count)| (std::cmp::Reverse(*count), op.code()));
+        counts
+    }
+
+    /// Executions per instruction code, i.e. per opcode and combination of
+    /// parameter modes, most frequent first.
+    pub fn mode_combinations(&self) -> Vec<(i64, u64)> {
+        let mut counts: Vec<(i64, u64)> = self.codes.iter().map(|(c, n)| (*c, *n)).collect();
+        counts.sort_by_key(|(code, count)| (std::cmp::Reverse(*count), *code));
+        counts
+    }
+
+    /// Executions per address, most frequent first.
+    pub fn hot_pcs(&self) -> Vec<(usize, u64)> {
+        let mut counts: Vec<(usize, u64)> = self.pcs.iter().map(|(pc, n)| (*pc, *n)).collect();
+        counts.sort_by_key(|(pc, count)| (std::cmp::Reverse(*count), *pc));
+        counts
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.total as f64
+        }
+    }
+
+    /// Ranked report of opcodes, mode combinations and the `top` hottest
+    /// addresses, disassembled from `memory`.
+    pub fn report(&self, memory: &Memory, top: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} instructions executed", self.total).unwrap();
+
+        writeln!(out, "\n{:<8} {:>12} {:>7}", "opcode", "count", "share").unwrap();
+        for (op, count) in self.opcodes() {
+            writeln!(
+                out,
+                "{:<8} {:>12} {:>6.2}%",
+                op.mnemonic(),
+                count,
+                self.share(count)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\n{:<8} {:>12} {:>7}  form", "code", "count", "share").unwrap();
+        for (code, count) in self.mode_combinations().into_iter().take(top) {
+            writeln!(
+                out,
+                "{:<8} {:>12} {:>6.2}%  {}",
+                code,
+                count,
+                self.share(count),
+                form(code)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\n{:<8} {:>12} {:>7}  instruction", "pc", "count", "share").unwrap();
+        for (pc, count) in self.hot_pcs().into_iter().take(top) {
+            writeln!(
+                out,
+                "{:<8} {:>12} {:>6.2}%  {}",
+                format!("{:04}", pc),
+                count,
+                self.share(count),
+                disassemble_at(memory, pc)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Every count as CSV with the columns `kind,key,count,share,text`, where
+    /// `kind` is `opcode`, `code` or `pc`.
+    pub fn to_csv(&self, memory: &Memory) -> String {
+        let mut out = String::from("kind,key,count,share,text\n");
+        for (op, count) in self.opcodes() {
+            writeln!(
+                out,
+                "opcode,{},{},{:.4},{}",
+                op.code(),
+                count,
+                self.share(count),
+                op.mnemonic()
+            )
+            .unwrap();
+        }
+        for (code, count) in self.mode_combinations() {
+            writeln!(
+                out,
+                "code,{},{},{:.4},{}",
+                code,
+                count,
+                self.share(count),
+                csv_field(&form(code))
+            )
+            .unwrap();
+        }
+        for (pc, count) in self.hot_pcs() {
+            writeln!(
+                out,
+                "pc,{},{},{:.4},{}",
+                pc,
+                count,
+                self.share(count),
+                csv_field(&disassemble_at(memory, pc))
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+// The shape of an instruction code, e.g. `ADD #, # -> [rb]` for 21101
+fn form(code: i64) -> String {
+    let op = match Opcode::from_code(code % 100) {
+        Some(op) => op,
+        None => return "???".to_string(),
+    };
+    let mut out = op.mnemonic().to_string();
+    for i in 0..op.num_params() {
+        let mode = match Mode::from_digit(instruction::mode_digit(code, i)) {
+            Some(Mode::Position) => "[]",
+            Some(Mode::Immediate) => "#",
+            Some(Mode::Relative) => "[rb]",
+            None => "?",
+        };
+        let separator = if op.writes() && i == op.num_params() - 1 {
+            " -> "
+        } else if i == 0 {
+            " "
+        } else {
+            ", "
+        };
+        out.push_str(separator);
+        out.push_str(mode);
+    }
+    out
+}
+
+fn disassemble_at(memory: &Memory, pc: usize) -> String {
+    // No instruction is longer than four words
+    let words: Vec<i64> = (0..4).map(|i| memory.get(pc.saturating_add(i))).collect();
+    match Instruction::decode(&words, 0) {
+        Ok(instruction) => instruction.to_string(),
+        Err(e) => format!("DATA {} ; {}", words[0], e),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -0,0 +1,227 @@
+//! Running a program as a pure function over many inputs, on all CPU cores.
+//!
+//! A `Search` knows the program and how to load a candidate into a fresh VM,
+//! by queueing input or patching memory. Each candidate gets its own VM,
+//! forked from one per worker so they share the program's pages, which runs
+//! until it halts, needs input it doesn't have, faults or runs out of steps.
+//! A predicate or objective then looks at the `Run` to decide:
+//!
+//! ```no_run
+//! use intcode::search::Search;
+//!
+//! let program = intcode::read_program("19.txt").unwrap();
+//! let search = Search::new(&program, |&(x, y): &(i64, i64), vm| {
+//!     vm.push_input(x);
+//!     vm.push_input(y);
+//! });
+//! let grid = (0..50).flat_map(|x| (0..50).map(move |y| (x, y)));
+//! let pulled = search.all(grid, |_, run| run.outputs == [1]);
+//! println!("{} points are pulled", pulled.len());
+//! ```
+//!
+//! Candidates are taken from the generator a few thousand at a time, and
+//! results never depend on how the work was split: `all` returns matches in
+//! the order the candidates came in, `first` the earliest match and `best`
+//! the earliest of the candidates with the highest score.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::VmError;
+use crate::vm::{Budget, StopReason, VM};
+
+// Keeps a candidate that never halts from stalling the search
+const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+// Candidates taken from the generator at a time, so it's never collected
+// whole
+const CHUNK: usize = 1 << 12;
+
+/// How a candidate's run ended.
+#[derive(Debug)]
+pub struct Run {
+    pub stop: Result<StopReason, VmError>,
+    /// Everything the program output.
+    pub outputs: Vec<i64>,
+    /// The machine as it stopped, e.g. to read memory from.
+    pub vm: VM,
+}
+
+impl Run {
+    /// Whether the program ran to its halt.
+    pub fn halted(&self) -> bool {
+        matches!(self.stop, Ok(StopReason::Halted))
+    }
+}
+
+// Loads a candidate into a VM
+type Setup<'a, T> = dyn Fn(&T, &mut VM) + Sync + 'a;
+
+pub struct Search<'a, T> {
+    program: &'a [i64],
+    setup: Box<Setup<'a, T>>,
+    max_steps: u64,
+    threads: usize,
+}
+
+impl<'a, T: Sync> Search<'a, T> {
+    /// A search over `program`, loading each candidate with `setup`.
+    pub fn new<F>(program: &'a [i64], setup: F) -> Search<'a, T>
+    where
+        F: Fn(&T, &mut VM) + Sync + 'a,
+    {
+        Search {
+            program,
+            setup: Box::new(setup),
+            max_steps: DEFAULT_MAX_STEPS,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Stops each run after `max_steps` instructions, ten million by default.
+    pub fn max_steps(mut self, max_steps: u64) -> Search<'a, T> {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Runs on `threads` workers instead of one per CPU core.
+    pub fn threads(mut self, threads: usize) -> Search<'a, T> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Every candidate `predicate` accepts, in the order they came in.
+    pub fn all<I, P>(&self, candidates: I, predicate: P) -> Vec<T>
+    where
+        I: IntoIterator<Item = T>,
+        P: Fn(&T, &Run) -> bool + Sync,
+    {
+        let mut found = Vec::new();
+        for_chunks(candidates, |chunk| {
+            let kept = self.evaluate(&chunk, false, |c, run| predicate(c, &run).then_some(()));
+            found.extend(pick(chunk, kept.into_iter().map(|(i, _)| i)));
+            true
+        });
+        found
+    }
+
+    /// The first candidate `predicate` accepts. Workers stop as soon as
+    /// nothing before a match is left to try, and no more candidates are
+    /// taken from the generator, so it can be endless.
+    pub fn first<I, P>(&self, candidates: I, predicate: P) -> Option<T>
+    where
+        I: IntoIterator<Item = T>,
+        P: Fn(&T, &Run) -> bool + Sync,
+    {
+        let mut found = None;
+        for_chunks(candidates, |chunk| {
+            let kept = self.evaluate(&chunk, true, |c, run| predicate(c, &run).then_some(()));
+            found = pick(chunk, kept.into_iter().map(|(i, _)| i).take(1)).pop();
+            found.is_none()
+        });
+        found
+    }
+
+    /// The candidate with the highest score and its score, the earliest one
+    /// on a tie. `objective` returns `None` for candidates that don't count.
+    pub fn best<I, K, O>(&self, candidates: I, objective: O) -> Option<(T, K)>
+    where
+        I: IntoIterator<Item = T>,
+        K: Ord + Send,
+        O: Fn(&T, &Run) -> Option<K> + Sync,
+    {
+        let mut best: Option<(T, K)> = None;
+        for_chunks(candidates, |chunk| {
+            let mut chunk_best: Option<(usize, K)> = None;
+            for (i, score) in self.evaluate(&chunk, false, |c, run| objective(c, &run)) {
+                if chunk_best.as_ref().is_none_or(|(_, s)| score > *s) {
+                    chunk_best = Some((i, score));
+                }
+            }
+            if let Some((index, score)) = chunk_best {
+                if best.as_ref().is_none_or(|(_, s)| score > *s) {
+                    best = Some((chunk.into_iter().nth(index).unwrap(), score));
+                }
+            }
+            true
+        });
+        best
+    }
+
+    // Runs the candidates and returns what `f` made of those it kept, by
+    // index. With `first` only the lowest index is guaranteed to be there.
+    fn evaluate<R, F>(&self, candidates: &[T], first: bool, f: F) -> Vec<(usize, R)>
+    where
+        R: Send,
+        F: Fn(&T, Run) -> Option<R> + Sync,
+    {
+        // Lowest index kept so far when looking for the first
+        let limit = AtomicUsize::new(candidates.len());
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for t in 0..self.threads {
+                let (limit, results, f) = (&limit, &results, &f);
+                scope.spawn(move || {
+                    let mut template = VM::new(self.program.to_vec());
+                    let mut kept = Vec::new();
+                    // Every worker takes every `threads`th candidate, so all
+                    // of them work on the earliest ones first
+                    for i in (t..candidates.len()).step_by(self.threads) {
+                        if first && i >= limit.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let mut vm = template.fork();
+                        (self.setup)(&candidates[i], &mut vm);
+                        let stop = vm.run_within(Budget::steps(self.max_steps));
+                        let outputs = vm.take_output();
+                        if let Some(r) = f(&candidates[i], Run { stop, outputs, vm }) {
+                            kept.push((i, r));
+                            if first {
+                                limit.fetch_min(i, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+                    }
+                    results.lock().unwrap().extend(kept);
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_unstable_by_key(|(i, _)| *i);
+        results
+    }
+}
+
+// Passes the candidates to `f` `CHUNK` at a time, until they run out or `f`
+// returns false
+fn for_chunks<T, I, F>(candidates: I, mut f: F)
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(Vec<T>) -> bool,
+{
+    let mut candidates = candidates.into_iter();
+    loop {
+        let chunk: Vec<T> = candidates.by_ref().take(CHUNK).collect();
+        if chunk.is_empty() || !f(chunk) {
+            return;
+        }
+    }
+}
+
+// The candidates at `indices`, which are in increasing order
+fn pick<T, I: IntoIterator<Item = usize>>(candidates: Vec<T>, indices: I) -> Vec<T> {
+    let mut indices = indices.into_iter().peekable();
+    candidates
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            let keep = indices.peek() == Some(i);
+            if keep {
+                indices.next();
+            }
+            keep
+        })
+        .map(|(_, c)| c)
+        .collect()
+}
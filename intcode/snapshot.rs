@@ -0,0 +1,213 @@
+//! Saving a VM to disk and picking up where it left off.
+//!
+//! Snapshots are plain text so they diff and survive hand editing:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! pc 2663
+//! relative_base 4810
+//! halted false
+//! waiting true
+//! limit none
+//! input 110,111,114,116,104,10
+//! output
+//! memory 0 109,4803,21101,3124,0,1,21102,1,13,0,...
+//! memory 1024 ...
+//! ```
+//!
+//! Memory is stored one allocated page per line, without trailing zeros.
+//! Attached input sources, output sinks, tracers and breakpoints aren't part
+//! of the machine's state and are not saved.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::memory::Memory;
+
+/// Current snapshot format version. Bump it when the format changes.
+pub const VERSION: u32 = 1;
+
+const MAGIC: &str = "intcode-snapshot";
+
+/// A VM's state at one point in time. See `VM::snapshot` and `VM::restore`.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) memory: Memory,
+    pub(crate) pc: usize,
+    pub(crate) relative_base: i64,
+    pub(crate) input: VecDeque<i64>,
+    pub(crate) output: Vec<i64>,
+    pub(crate) halted: bool,
+    pub(crate) waiting_for_input: bool,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The file doesn't start with the snapshot header.
+    NotASnapshot,
+    /// The snapshot was written in a format version this build can't read.
+    Version { found: u32, expected: u32 },
+    /// One based line that couldn't be parsed.
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::NotASnapshot => write!(f, "not an Intcode snapshot"),
+            SnapshotError::Version { found, expected } => write!(
+                f,
+                "snapshot format version {} is not supported (expected {})",
+                found, expected
+            ),
+            SnapshotError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+impl Snapshot {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        Snapshot::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines().enumerate();
+        let version = match lines.next().map(|(_, l)| l.split_whitespace().collect::<Vec<_>>()) {
+            Some(ref words) if words.len() == 2 && words[0] == MAGIC => words[1]
+                .parse()
+                .map_err(|_| SnapshotError::NotASnapshot)?,
+            _ => return Err(SnapshotError::NotASnapshot),
+        };
+        if version != VERSION {
+            return Err(SnapshotError::Version {
+                found: version,
+                expected: VERSION,
+            });
+        }
+
+        let mut snapshot = Snapshot {
+            memory: Memory::default(),
+            pc: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            halted: false,
+            waiting_for_input: false,
+        };
+        let mut seen = Vec::new();
+        for (idx, line) in lines {
+            let invalid = |message: String| SnapshotError::Invalid {
+                line: idx + 1,
+                message,
+            };
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => (line.trim(), ""),
+            };
+            if key.is_empty() {
+                continue;
+            }
+            if key != "memory" {
+                if seen.contains(&key) {
+                    return Err(invalid(format!("duplicate `{}`", key)));
+                }
+                seen.push(key);
+            }
+            match key {
+                "pc" => snapshot.pc = parse(value).map_err(invalid)?,
+                "relative_base" => snapshot.relative_base = parse(value).map_err(invalid)?,
+                "halted" => snapshot.halted = parse(value).map_err(invalid)?,
+                "waiting" => snapshot.waiting_for_input = parse(value).map_err(invalid)?,
+                "limit" if value == "none" => snapshot.memory.set_limit(None),
+                "limit" => snapshot.memory.set_limit(Some(parse(value).map_err(invalid)?)),
+                "input" => snapshot.input = parse_list(value).map_err(invalid)?.into(),
+                "output" => snapshot.output = parse_list(value).map_err(invalid)?,
+                "memory" => {
+                    let (start, words) = value.split_at(value.find(' ').unwrap_or(value.len()));
+                    let start: usize = parse(start).map_err(invalid)?;
+                    for (i, v) in parse_list(words.trim()).map_err(invalid)?.iter().enumerate() {
+                        let addr = start.checked_add(i).ok_or_else(|| {
+                            invalid("memory runs past the last address".to_string())
+                        })?;
+                        snapshot.memory.set(addr, *v);
+                    }
+                }
+                _ => return Err(invalid(format!("unknown field `{}`", key))),
+            }
+        }
+        for key in &["pc", "relative_base", "halted"] {
+            if !seen.contains(key) {
+                return Err(SnapshotError::Invalid {
+                    line: text.lines().count(),
+                    message: format!("missing `{}`", key),
+                });
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Formats the snapshot in the file format `Snapshot::parse` reads.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        writeln!(f, "pc {}", self.pc)?;
+        writeln!(f, "relative_base {}", self.relative_base)?;
+        writeln!(f, "halted {}", self.halted)?;
+        writeln!(f, "waiting {}", self.waiting_for_input)?;
+        match self.memory.limit() {
+            Some(limit) => writeln!(f, "limit {}", limit)?,
+            None => writeln!(f, "limit none")?,
+        }
+        writeln!(f, "input {}", join(self.input.iter()))?;
+        writeln!(f, "output {}", join(self.output.iter()))?;
+        for (start, words) in self.memory.pages() {
+            let len = words.iter().rposition(|w| *w != 0).map_or(0, |i| i + 1);
+            if len > 0 {
+                writeln!(f, "memory {} {}", start, join(words[..len].iter()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join<'a, I: Iterator<Item = &'a i64>>(values: I) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid value `{}`", s))
+}
+
+fn parse_list(s: &str) -> Result<Vec<i64>, String> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',').map(|v| parse(v.trim())).collect()
+}
@@ -0,0 +1,562 @@
+//! Symbolic execution of straight-line Intcode, and solving for inputs.
+//!
+//! Some programs, like day2's, are a pure function of a few words patched in
+//! before they run. `Symbolic::run` executes such a program with those words
+//! left as symbols, so every word ends up as a polynomial over them instead
+//! of a number. `solve` uses that to find the symbol values that leave a
+//! given word at a target: it only has to substitute all but the last
+//! symbol, and solves for the last one directly when it's linear.
+//!
+//! Symbolic execution gives up on anything whose outcome would depend on a
+//! symbol: a jump on one, even through a comparison, writing to an address
+//! computed from one, or running an opcode computed from one. Reading
+//! through such an address is fine as long as the value read isn't used for
+//! any of those or asked for at the end. When it gives up, `solve` falls
+//! back to running the program for every combination of values, spread
+//! over all CPU cores with `search::Search`.
+//!
+//! ```no_run
+//! use intcode::symbolic;
+//!
+//! let program = intcode::read_program("2.txt").unwrap();
+//! let solution = symbolic::solve(&program, &[(1, 0..=99), (2, 0..=99)], 0, 19690720).unwrap();
+//! println!("{:?} using {:?}", solution.values, solution.method);
+//! ```
+//!
+//! Polynomials use wrapping arithmetic, like the VM does by default, so they
+//! agree with a real run even when it overflows.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::instruction::{mode_digit, Opcode};
+use crate::search::Search;
+
+/// Runs are cut off after this many instructions, symbolic or not.
+pub const MAX_STEPS: u64 = 10_000_000;
+
+/// Most combinations of values `solve` runs the program for when it can't
+/// solve symbolically.
+pub const MAX_COMBINATIONS: u64 = 1 << 32;
+
+/// A polynomial over the symbols, with wrapping coefficients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poly {
+    // Coefficient of each term, keyed by the power of every symbol. Zero
+    // coefficients are left out.
+    terms: BTreeMap<Vec<u32>, i64>,
+    symbols: usize,
+}
+
+impl Poly {
+    fn constant(symbols: usize, value: i64) -> Poly {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(vec![0; symbols], value);
+        }
+        Poly { terms, symbols }
+    }
+
+    fn symbol(symbols: usize, index: usize) -> Poly {
+        let mut powers = vec![0; symbols];
+        powers[index] = 1;
+        let mut terms = BTreeMap::new();
+        terms.insert(powers, 1);
+        Poly { terms, symbols }
+    }
+
+    fn add_term(&mut self, powers: Vec<u32>, coefficient: i64) {
+        let c = self.terms.entry(powers).or_insert(0);
+        *c = c.wrapping_add(coefficient);
+        if *c == 0 {
+            self.terms.retain(|_, c| *c != 0);
+        }
+    }
+
+    fn add(&self, other: &Poly) -> Poly {
+        let mut sum = self.clone();
+        for (powers, c) in &other.terms {
+            sum.add_term(powers.clone(), *c);
+        }
+        sum
+    }
+
+    fn mul(&self, other: &Poly) -> Poly {
+        let mut product = Poly::constant(self.symbols, 0);
+        for (p, a) in &self.terms {
+            for (q, b) in &other.terms {
+                let powers = p.iter().zip(q).map(|(x, y)| x + y).collect();
+                product.add_term(powers, a.wrapping_mul(*b));
+            }
+        }
+        product
+    }
+
+    /// The value, if it doesn't depend on any symbol.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((powers, c)) if self.terms.len() == 1 && powers.iter().all(|p| *p == 0) => {
+                Some(*c)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// The highest power of symbol `index` in any term.
+    pub fn degree(&self, index: usize) -> u32 {
+        self.terms.keys().map(|p| p[index]).max().unwrap_or(0)
+    }
+
+    /// Evaluates the polynomial with `values[i]` for symbol `i`.
+    pub fn eval(&self, values: &[i64]) -> i64 {
+        self.terms.iter().fold(0i64, |sum, (powers, c)| {
+            let term = powers
+                .iter()
+                .zip(values)
+                .fold(*c, |t, (p, v)| t.wrapping_mul(v.wrapping_pow(*p)));
+            sum.wrapping_add(term)
+        })
+    }
+
+    /// Replaces symbol `index` with `value`.
+    pub fn substitute(&self, index: usize, value: i64) -> Poly {
+        let mut out = Poly::constant(self.symbols, 0);
+        for (powers, c) in &self.terms {
+            let mut powers = powers.clone();
+            let c = c.wrapping_mul(value.wrapping_pow(powers[index]));
+            powers[index] = 0;
+            out.add_term(powers, c);
+        }
+        out
+    }
+
+    /// Formats the polynomial, highest powers first, calling symbol `i`
+    /// `names[i]`.
+    pub fn format(&self, names: &[String]) -> String {
+        let mut out = String::new();
+        for (powers, c) in self.terms.iter().rev() {
+            let factors: Vec<String> = powers
+                .iter()
+                .zip(names)
+                .filter(|(p, _)| **p > 0)
+                .map(|(p, name)| match p {
+                    1 => name.clone(),
+                    p => format!("{}^{}", name, p),
+                })
+                .collect();
+            let magnitude = c.unsigned_abs();
+            if out.is_empty() {
+                if *c < 0 {
+                    out.push('-');
+                }
+            } else {
+                out.push_str(if *c < 0 { " - " } else { " + " });
+            }
+            if factors.is_empty() {
+                out.push_str(&magnitude.to_string());
+            } else {
+                if magnitude != 1 {
+                    out.push_str(&format!("{}*", magnitude));
+                }
+                out.push_str(&factors.join("*"));
+            }
+        }
+        if out.is_empty() {
+            out.push('0');
+        }
+        out
+    }
+}
+
+/// Why a program couldn't be run symbolically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The opcode of an instruction depends on a symbol.
+    Opcode,
+    /// An instruction writes to, or adjusts the relative base by, something
+    /// that depends on a symbol.
+    Address,
+    /// A jump depends on a symbol, possibly through a comparison.
+    Branch,
+    /// Input and output aren't supported.
+    Io,
+    /// The word asked for was read through an address that depends on a
+    /// symbol.
+    Indirect,
+    /// The instruction would fault whatever the symbols are.
+    Fault,
+    /// The program ran for `MAX_STEPS` instructions without halting.
+    Steps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported {
+    /// Address of the instruction symbolic execution stopped at.
+    pub pc: usize,
+    pub reason: Reason,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.reason {
+            Reason::Opcode => "opcode depends on a symbol",
+            Reason::Address => "address depends on a symbol",
+            Reason::Branch => "jump depends on a symbol",
+            Reason::Io => "input and output aren't supported",
+            Reason::Indirect => "result was read through an address that depends on a symbol",
+            Reason::Fault => "instruction faults",
+            Reason::Steps => "program didn't halt",
+        };
+        write!(f, "{} at {}", reason, self.pc)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Known(Poly),
+    // Read through an address that depends on a symbol
+    Unknown,
+}
+
+impl Value {
+    fn constant(&self) -> Option<i64> {
+        match self {
+            Value::Known(p) => p.as_constant(),
+            Value::Unknown => None,
+        }
+    }
+}
+
+/// Memory of a program that ran to its halt with some words left as
+/// symbols.
+#[derive(Debug, Clone)]
+pub struct Symbolic {
+    symbols: Vec<usize>,
+    program: Vec<i64>,
+    // Words written since the start, and the symbols
+    written: HashMap<usize, Value>,
+    pc: usize,
+    relative_base: i64,
+}
+
+impl Symbolic {
+    /// Runs `program` with the word at `symbols[i]` as symbol `i`, until it
+    /// halts.
+    pub fn run(program: &[i64], symbols: &[usize]) -> Result<Symbolic, Unsupported> {
+        let n = symbols.len();
+        let mut machine = Symbolic {
+            symbols: symbols.to_vec(),
+            program: program.to_vec(),
+            written: symbols
+                .iter()
+                .enumerate()
+                .map(|(i, addr)| (*addr, Value::Known(Poly::symbol(n, i))))
+                .collect(),
+            pc: 0,
+            relative_base: 0,
+        };
+        for _ in 0..MAX_STEPS {
+            if machine.step()? {
+                return Ok(machine);
+            }
+        }
+        Err(machine.unsupported(Reason::Steps))
+    }
+
+    /// The word at `addr` as a polynomial over the symbols, or `None` if it
+    /// was read through an address that depends on one.
+    pub fn get(&self, addr: usize) -> Option<Poly> {
+        match self.load(addr) {
+            Value::Known(p) => Some(p),
+            Value::Unknown => None,
+        }
+    }
+
+    /// The word at `addr` as a formula over the symbols, which are named
+    /// after their addresses, e.g. `460800*mem[1] + mem[2] + 337061`.
+    pub fn formula(&self, addr: usize) -> Option<String> {
+        let names: Vec<String> = self.symbols.iter().map(|a| format!("mem[{}]", a)).collect();
+        self.get(addr).map(|p| p.format(&names))
+    }
+
+    fn unsupported(&self, reason: Reason) -> Unsupported {
+        Unsupported {
+            pc: self.pc,
+            reason,
+        }
+    }
+
+    fn load(&self, addr: usize) -> Value {
+        match self.written.get(&addr) {
+            Some(v) => v.clone(),
+            None => Value::Known(Poly::constant(
+                self.symbols.len(),
+                self.program.get(addr).copied().unwrap_or(0),
+            )),
+        }
+    }
+
+    // The word at `addr`, which has to be a number
+    fn word(&self, addr: usize, reason: Reason) -> Result<i64, Unsupported> {
+        self.load(addr)
+            .constant()
+            .ok_or_else(|| self.unsupported(reason))
+    }
+
+    fn address(&self, addr: i64) -> Result<usize, Unsupported> {
+        if addr < 0 {
+            Err(self.unsupported(Reason::Fault))
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    // Where parameter `i` points, or `None` if that depends on a symbol
+    fn location(&self, code: i64, i: usize) -> Result<Option<usize>, Unsupported> {
+        let p = match self.load(self.pc + 1 + i).constant() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        match mode_digit(code, i) {
+            0 => self.address(p).map(Some),
+            2 => self.address(self.relative_base.wrapping_add(p)).map(Some),
+            _ => Err(self.unsupported(Reason::Fault)),
+        }
+    }
+
+    fn param(&self, code: i64, i: usize) -> Result<Value, Unsupported> {
+        if mode_digit(code, i) == 1 {
+            return Ok(self.load(self.pc + 1 + i));
+        }
+        Ok(match self.location(code, i)? {
+            Some(addr) => self.load(addr),
+            None => Value::Unknown,
+        })
+    }
+
+    // Runs one instruction and says whether the program halted
+    fn step(&mut self) -> Result<bool, Unsupported> {
+        let code = self.word(self.pc, Reason::Opcode)?;
+        let op = Opcode::from_code(code % 100).ok_or_else(|| self.unsupported(Reason::Fault))?;
+        let mut next = self.pc + 1 + op.num_params();
+        match op {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => {
+                let (a, b) = (self.param(code, 0)?, self.param(code, 1)?);
+                let dest = self
+                    .location(code, 2)?
+                    .ok_or_else(|| self.unsupported(Reason::Address))?;
+                let n = self.symbols.len();
+                let value = match (op, a, b) {
+                    (Opcode::Add, Value::Known(a), Value::Known(b)) => Value::Known(a.add(&b)),
+                    (Opcode::Mul, Value::Known(a), Value::Known(b)) => Value::Known(a.mul(&b)),
+                    (op, a, b) => match (a.constant(), b.constant()) {
+                        (Some(a), Some(b)) if op == Opcode::LessThan => {
+                            Value::Known(Poly::constant(n, (a < b) as i64))
+                        }
+                        (Some(a), Some(b)) if op == Opcode::Equals => {
+                            Value::Known(Poly::constant(n, (a == b) as i64))
+                        }
+                        // Only a jump on it is a problem
+                        _ => Value::Unknown,
+                    },
+                };
+                self.written.insert(dest, value);
+            }
+            Opcode::Input | Opcode::Output => return Err(self.unsupported(Reason::Io)),
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let condition = self.param(code, 0)?.constant();
+                let target = self.param(code, 1)?.constant();
+                let (condition, target) = match (condition, target) {
+                    (Some(c), Some(t)) => (c, t),
+                    _ => return Err(self.unsupported(Reason::Branch)),
+                };
+                if (condition != 0) == (op == Opcode::JumpIfTrue) {
+                    next = self.address(target)?;
+                }
+            }
+            Opcode::AdjustBase => {
+                let n = self
+                    .param(code, 0)?
+                    .constant()
+                    .ok_or_else(|| self.unsupported(Reason::Address))?;
+                self.relative_base = self.relative_base.wrapping_add(n);
+            }
+            Opcode::Halt => return Ok(true),
+        }
+        self.pc = next;
+        Ok(false)
+    }
+}
+
+/// `solve` couldn't solve symbolically and there are too many combinations
+/// to run the program for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyCombinations {
+    pub combinations: u128,
+    /// Why symbolic execution gave up.
+    pub unsupported: Unsupported,
+}
+
+impl fmt::Display for TooManyCombinations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, and {} combinations are too many to try",
+            self.unsupported, self.combinations
+        )
+    }
+}
+
+impl Error for TooManyCombinations {}
+
+/// How `solve` went about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// From the formula found by symbolic execution.
+    Symbolic,
+    /// By running the program for every combination of values, because
+    /// symbolic execution gave up as described.
+    BruteForce(Unsupported),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// A value for each unknown, or `None` if no combination works.
+    pub values: Option<Vec<i64>>,
+    pub method: Method,
+}
+
+/// Finds values for the words at `unknowns`, each within its range, that
+/// leave `target` at address `addr` once the program halts.
+///
+/// Combinations are ordered by the value of the first unknown, then the
+/// second and so on, and the first one that works is returned whichever
+/// method is used. When brute forcing, runs that fault, wait for input or
+/// don't halt within `MAX_STEPS` instructions don't count, and there can
+/// be at most `MAX_COMBINATIONS` of them.
+pub fn solve(
+    program: &[i64],
+    unknowns: &[(usize, RangeInclusive<i64>)],
+    addr: usize,
+    target: i64,
+) -> Result<Solution, TooManyCombinations> {
+    let symbols: Vec<usize> = unknowns.iter().map(|(a, _)| *a).collect();
+    let ranges: Vec<RangeInclusive<i64>> = unknowns.iter().map(|(_, r)| r.clone()).collect();
+    let formula = Symbolic::run(program, &symbols).and_then(|s| {
+        s.get(addr).ok_or(Unsupported {
+            pc: s.pc,
+            reason: Reason::Indirect,
+        })
+    });
+    match formula {
+        Ok(poly) => {
+            let mut values = Vec::new();
+            let found = solve_poly(&poly, &ranges, target, &mut values);
+            Ok(Solution {
+                values: if found { Some(values) } else { None },
+                method: Method::Symbolic,
+            })
+        }
+        Err(unsupported) => Ok(Solution {
+            values: brute_force(program, unknowns, addr, target, unsupported)?,
+            method: Method::BruteForce(unsupported),
+        }),
+    }
+}
+
+// Substitutes values for every symbol but the last, in order, and solves for
+// that one. Pushes the first solution onto `values`.
+fn solve_poly(
+    poly: &Poly,
+    ranges: &[RangeInclusive<i64>],
+    target: i64,
+    values: &mut Vec<i64>,
+) -> bool {
+    let index = values.len();
+    let range = match ranges.get(index) {
+        Some(r) => r.clone(),
+        None => return poly.as_constant() == Some(target),
+    };
+    if index + 1 < ranges.len() {
+        for v in range {
+            values.push(v);
+            if solve_poly(&poly.substitute(index, v), ranges, target, values) {
+                return true;
+            }
+            values.pop();
+        }
+        return false;
+    }
+
+    if poly.degree(index) <= 1 {
+        let c = poly.substitute(index, 0).eval(&[]) as i128;
+        let a = poly.substitute(index, 1).eval(&[]) as i128 - c;
+        let (lo, hi) = (*range.start() as i128, *range.end() as i128);
+        let fits = |x: i128| x >= i64::MIN as i128 && x <= i64::MAX as i128;
+        // Without wrapping a line crosses the target at most once
+        if lo <= hi && fits(a * lo + c) && fits(a * hi + c) {
+            let d = target as i128 - c;
+            let x = match a {
+                0 if d == 0 => Some(lo),
+                0 => None,
+                a if d % a == 0 => Some(d / a),
+                _ => None,
+            };
+            return match x {
+                Some(x) if x >= lo && x <= hi => {
+                    values.push(x as i64);
+                    true
+                }
+                _ => false,
+            };
+        }
+    }
+    let found = range
+        .into_iter()
+        .find(|v| poly.substitute(index, *v).eval(&[]) == target);
+    values.extend(found);
+    found.is_some()
+}
+
+// Runs the program for every combination on all cores, and returns the first
+// that works in the order `solve` describes
+fn brute_force(
+    program: &[i64],
+    unknowns: &[(usize, RangeInclusive<i64>)],
+    addr: usize,
+    target: i64,
+    unsupported: Unsupported,
+) -> Result<Option<Vec<i64>>, TooManyCombinations> {
+    let sizes: Vec<u128> = unknowns
+        .iter()
+        .map(|(_, r)| (*r.end() as i128 - *r.start() as i128 + 1).max(0) as u128)
+        .collect();
+    let combinations = sizes.iter().fold(1u128, |t, s| t.saturating_mul(*s));
+    if combinations > MAX_COMBINATIONS as u128 {
+        return Err(TooManyCombinations {
+            combinations,
+            unsupported,
+        });
+    }
+    let search = Search::new(program, |values: &Vec<i64>, vm| {
+        for ((a, _), v) in unknowns.iter().zip(values) {
+            vm.set_state(*a, *v);
+        }
+    })
+    .max_steps(MAX_STEPS);
+    // Combination `i` has the first unknown as its most significant digit
+    let combination = |mut i: u128| -> Vec<i64> {
+        let mut values = vec![0; sizes.len()];
+        for (j, size) in sizes.iter().enumerate().rev() {
+            values[j] = (*unknowns[j].1.start() as i128 + (i % size) as i128) as i64;
+            i /= size;
+        }
+        values
+    };
+    Ok(search.first((0..combinations).map(combination), |_, run| {
+        run.halted() && run.vm.get_state(addr) == target
+    }))
+}
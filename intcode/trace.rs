@@ -0,0 +1,207 @@
+//! Instruction level execution traces as JSON Lines.
+//!
+//! Attach a `Tracer` with `VM::attach_tracer` and every executed instruction
+//! that passes its filters is written as one JSON object:
+//!
+//! ```text
+//! {"step":41,"pc":25,"opcode":203,"mnemonic":"IN","relative_base":1000,
+//!  "operands":[{"mode":"relative","value":0,"resolved":1000,"write":true}],
+//!  "writes":[{"addr":1000,"value":1}],"input":1}
+//! ```
+//!
+//! (shown wrapped). `resolved` is the value read, or for the written operand
+//! the address written to. `input`, `output` and `error` only appear when
+//! the instruction read input, produced output or faulted.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::VmError;
+use crate::instruction::{Mode, Opcode};
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    pc_range: Option<Range<usize>>,
+    opcodes: HashSet<Opcode>,
+    // Instructions seen since the tracer was attached, traced or not
+    steps: u64,
+    // Whether the instruction being executed passes the filters
+    active: bool,
+    record: Record,
+    error: Option<io::Error>,
+}
+
+#[derive(Default)]
+pub(crate) struct Record {
+    pc: usize,
+    code: i64,
+    relative_base: i64,
+    operands: Vec<(Mode, i64, i64, bool)>,
+    writes: Vec<(usize, i64)>,
+    input: Option<i64>,
+    output: Option<i64>,
+}
+
+impl Tracer {
+    /// Traces every instruction to `out`.
+    pub fn new<W>(out: W) -> Tracer
+    where
+        W: Write + Send + 'static,
+    {
+        Tracer {
+            out: Box::new(out),
+            pc_range: None,
+            opcodes: HashSet::new(),
+            steps: 0,
+            active: false,
+            record: Record::default(),
+            error: None,
+        }
+    }
+
+    /// Traces to a newly created file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+        Ok(Tracer::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Only traces instructions whose address is in `range`.
+    pub fn pc_range(mut self, range: Range<usize>) -> Tracer {
+        self.pc_range = Some(range);
+        self
+    }
+
+    /// Only traces instructions with this opcode. Can be given several times;
+    /// with none, every opcode is traced.
+    pub fn opcode(mut self, opcode: Opcode) -> Tracer {
+        self.opcodes.insert(opcode);
+        self
+    }
+
+    /// Flushes the trace, returning the first error hit while writing it.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+
+    pub(crate) fn begin(&mut self, pc: usize, code: i64, relative_base: i64) {
+        self.steps += 1;
+        self.active = self.pc_range.as_ref().is_none_or(|r| r.contains(&pc))
+            && (self.opcodes.is_empty()
+                || Opcode::from_code(code % 100).is_some_and(|op| self.opcodes.contains(&op)));
+        if self.active {
+            self.record = Record {
+                pc,
+                code,
+                relative_base,
+                ..Record::default()
+            };
+        }
+    }
+
+    /// The record of the instruction being executed, if it's being traced.
+    pub(crate) fn record(&mut self) -> Option<&mut Record> {
+        if self.active {
+            Some(&mut self.record)
+        } else {
+            None
+        }
+    }
+
+    /// Drops the current instruction, e.g. because it's waiting for input and
+    /// will run again later.
+    pub(crate) fn cancel(&mut self) {
+        self.steps -= 1;
+        self.active = false;
+    }
+
+    pub(crate) fn end(&mut self, error: Option<&VmError>) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        if self.error.is_some() {
+            return;
+        }
+        let line = self.format(error);
+        if let Err(e) = self.out.write_all(line.as_bytes()) {
+            self.error = Some(e);
+        }
+    }
+
+    fn format(&self, error: Option<&VmError>) -> String {
+        let r = &self.record;
+        let mut line = String::with_capacity(192);
+        write!(
+            line,
+            "{{\"step\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"relative_base\":{},\"operands\":[",
+            self.steps,
+            r.pc,
+            r.code,
+            Opcode::from_code(r.code % 100).map_or("???", |op| op.mnemonic()),
+            r.relative_base
+        )
+        .unwrap();
+        for (i, (mode, value, resolved, write)) in r.operands.iter().enumerate() {
+            write!(
+                line,
+                "{}{{\"mode\":\"{}\",\"value\":{},\"resolved\":{}",
+                if i == 0 { "" } else { "," },
+                mode.name(),
+                value,
+                resolved
+            )
+            .unwrap();
+            if *write {
+                line.push_str(",\"write\":true");
+            }
+            line.push('}');
+        }
+        line.push_str("],\"writes\":[");
+        for (i, (addr, value)) in r.writes.iter().enumerate() {
+            write!(
+                line,
+                "{}{{\"addr\":{},\"value\":{}}}",
+                if i == 0 { "" } else { "," },
+                addr,
+                value
+            )
+            .unwrap();
+        }
+        line.push(']');
+        if let Some(v) = r.input {
+            write!(line, ",\"input\":{}", v).unwrap();
+        }
+        if let Some(v) = r.output {
+            write!(line, ",\"output\":{}", v).unwrap();
+        }
+        if let Some(e) = error {
+            write!(line, ",\"error\":{}", crate::json::string(&e.to_string())).unwrap();
+        }
+        line.push_str("}\n");
+        line
+    }
+}
+
+impl Record {
+    pub(crate) fn operand(&mut self, mode: Mode, value: i64, resolved: i64, write: bool) {
+        self.operands.push((mode, value, resolved, write));
+    }
+
+    pub(crate) fn write(&mut self, addr: usize, value: i64) {
+        self.writes.push((addr, value));
+    }
+
+    pub(crate) fn input(&mut self, value: i64) {
+        self.input = Some(value);
+    }
+
+    pub(crate) fn output(&mut self, value: i64) {
+        self.output = Some(value);
+    }
+}
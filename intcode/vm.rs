@@ -0,0 +1,1152 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::cache::{DecodeCache, Decoded};
+use crate::codemap::CodeMap;
+use crate::error::{ErrorKind, VmError};
+use crate::exec::{self, Receiver, Sender};
+use crate::instruction::{mode_digit, Mode, Opcode};
+use crate::io::{InputSource, OutputSink};
+use crate::memory::Memory;
+use crate::profile::Profiler;
+use crate::snapshot::Snapshot;
+use crate::trace::{Record, Tracer};
+
+/// Why `VM::resume` handed control back to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed opcode 99. Resuming again is a no-op.
+    Halted,
+    /// The program is blocked on an input instruction with an empty input
+    /// queue. Push some input and resume to continue.
+    NeedsInput,
+    /// The program produced a value.
+    Output(i64),
+    /// The instruction count or deadline of the `Budget` ran out.
+    BudgetExhausted,
+    /// Execution reached a breakpoint. The instruction at this address has not
+    /// run yet; resuming executes it.
+    Breakpoint(usize),
+}
+
+/// Limits on how long a single `resume_within` or `run_within` call may run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub max_steps: Option<u64>,
+    pub deadline: Option<Instant>,
+}
+
+// The clock is only read every this many instructions
+const DEADLINE_CHECK_INTERVAL: u64 = 1 << 12;
+
+// Instructions `run_async` executes before letting other tasks run
+const ASYNC_SLICE: u64 = 1 << 16;
+
+impl Budget {
+    /// No limits at all.
+    pub fn unlimited() -> Budget {
+        Budget::default()
+    }
+
+    /// At most `max_steps` instructions.
+    pub fn steps(max_steps: u64) -> Budget {
+        Budget {
+            max_steps: Some(max_steps),
+            deadline: None,
+        }
+    }
+
+    /// Until `deadline` passes.
+    pub fn until(deadline: Instant) -> Budget {
+        Budget {
+            max_steps: None,
+            deadline: Some(deadline),
+        }
+    }
+
+    /// For `timeout` from now.
+    pub fn timeout(timeout: Duration) -> Budget {
+        Budget::until(Instant::now() + timeout)
+    }
+
+    /// Also stops after `max_steps` instructions.
+    pub fn with_steps(self, max_steps: u64) -> Budget {
+        Budget {
+            max_steps: Some(max_steps),
+            ..self
+        }
+    }
+}
+
+/// How the VM executes instructions. Both engines give identical results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Decodes every instruction each time it runs.
+    Interpreter,
+    /// Decodes each instruction once and reuses it until the program writes
+    /// over it. This is the default.
+    Cached,
+}
+
+/// What happens when an addition or multiplication doesn't fit in an `i64`.
+/// The same program gives the same results in debug and release builds under
+/// every policy. For numbers of any size, see `bigint::BigVm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// Wraps around in two's complement. This is the default.
+    Wrapping,
+    /// Stops with an `ErrorKind::Overflow` fault at the instruction.
+    Checked,
+    /// Clamps to `i64::MIN` or `i64::MAX`.
+    Saturating,
+}
+
+impl Arithmetic {
+    fn add(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_add(b)),
+            Arithmetic::Checked => a.checked_add(b),
+            Arithmetic::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    fn mul(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_mul(b)),
+            Arithmetic::Checked => a.checked_mul(b),
+            Arithmetic::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+pub struct VM {
+    state: Memory,
+    engine: Engine,
+    arithmetic: Arithmetic,
+    cache: DecodeCache,
+    pc: usize,
+    input: VecDeque<i64>,
+    output: Vec<i64>,
+    // Consulted once `input` is empty
+    source: Option<Box<dyn InputSource>>,
+    // Receives outputs instead of `resume` stopping on them
+    sink: Option<Box<dyn OutputSink>>,
+    halted: bool,
+    // Stopped on an input instruction with nothing to read
+    waiting_for_input: bool,
+    // Set while `run_within` runs, which buffers outputs instead of stopping
+    buffering: bool,
+    relative_base: i64,
+    breakpoints: HashSet<usize>,
+    opcode_breakpoints: HashSet<Opcode>,
+    // Breakpoint we last stopped at, so resuming steps over it
+    paused_at: Option<usize>,
+    // Address and raw value of the instruction being executed
+    instruction_pc: usize,
+    instruction: i64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    code_map: Option<CodeMap>,
+    // Instructions run to completion over the VM's lifetime
+    executed: u64,
+}
+
+// Outcome of executing a single instruction
+enum Step {
+    Continue,
+    Output(i64),
+    NeedsInput,
+    Halted,
+}
+
+impl VM {
+    pub fn new(initial_state: Vec<i64>) -> VM {
+        VM {
+            state: Memory::new(&initial_state),
+            engine: Engine::Cached,
+            arithmetic: Arithmetic::Wrapping,
+            cache: DecodeCache::prime(&initial_state),
+            pc: 0,
+            output: Vec::new(),
+            input: VecDeque::new(),
+            source: None,
+            sink: None,
+            halted: false,
+            waiting_for_input: false,
+            buffering: false,
+            relative_base: 0,
+            breakpoints: HashSet::new(),
+            opcode_breakpoints: HashSet::new(),
+            paused_at: None,
+            instruction_pc: 0,
+            instruction: 0,
+            tracer: None,
+            profiler: None,
+            code_map: None,
+            executed: 0,
+        }
+    }
+
+    fn fault(&self, kind: ErrorKind) -> VmError {
+        VmError {
+            kind,
+            pc: self.instruction_pc,
+            opcode: self.instruction,
+            relative_base: self.relative_base,
+        }
+    }
+
+    fn address(&self, addr: i64) -> Result<usize, VmError> {
+        self.check_address(addr).map_err(|kind| self.fault(kind))
+    }
+
+    #[inline(always)]
+    fn check_address(&self, addr: i64) -> Result<usize, ErrorKind> {
+        if addr < 0 {
+            Err(ErrorKind::NegativeAddress(addr))
+        } else if self.state.limit().is_some_and(|l| addr as usize >= l) {
+            Err(ErrorKind::AddressOutOfRange(addr))
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    // Relative addresses wrap, so an overflowing one faults as negative or
+    // out of range whatever the build profile
+    #[inline(always)]
+    fn relative(&self, offset: i64) -> i64 {
+        offset.wrapping_add(self.relative_base)
+    }
+
+    fn add(&self, a: i64, b: i64) -> Result<i64, VmError> {
+        self.arithmetic
+            .add(a, b)
+            .ok_or_else(|| self.fault(ErrorKind::Overflow))
+    }
+
+    fn mul(&self, a: i64, b: i64) -> Result<i64, VmError> {
+        self.arithmetic
+            .mul(a, b)
+            .ok_or_else(|| self.fault(ErrorKind::Overflow))
+    }
+
+    fn read(&self, addr: i64) -> Result<i64, VmError> {
+        Ok(self.state.get(self.address(addr)?))
+    }
+
+    // Reads a word through an operand, as opposed to fetching code
+    fn read_data(&mut self, addr: i64) -> Result<i64, VmError> {
+        let addr = self.address(addr)?;
+        if let Some(map) = self.code_map.as_mut() {
+            map.read(addr);
+        }
+        Ok(self.state.get(addr))
+    }
+
+    // Resolves an address an operand writes to, trapping writes to executed
+    // code if the code map says to
+    fn write_address(&self, addr: i64) -> Result<usize, VmError> {
+        let addr = self.address(addr)?;
+        if let Some(instruction) = self.code_map.as_ref().and_then(|m| m.trap(addr)) {
+            return Err(self.fault(ErrorKind::SelfModifyingCode { addr, instruction }));
+        }
+        Ok(addr)
+    }
+
+    fn write(&mut self, addr: usize, val: i64) {
+        if let Some(map) = self.code_map.as_mut() {
+            let state = &self.state;
+            map.written(self.instruction_pc, addr, val, |a| state.get(a));
+        }
+        self.store(addr, val);
+        self.trace(|r| r.write(addr, val));
+    }
+
+    // Adds to the trace record of the current instruction, if it's traced
+    fn trace<F: FnOnce(&mut Record)>(&mut self, f: F) {
+        if let Some(record) = self.tracer.as_mut().and_then(|t| t.record()) {
+            f(record);
+        }
+    }
+
+    fn next_value(&mut self) -> Result<i64, VmError> {
+        let v = self.read(self.pc as i64)?;
+        self.pc += 1;
+        Ok(v)
+    }
+
+    // Reads parameter `i` (zero based) of the instruction `code`
+    fn param(&mut self, code: i64, i: usize) -> Result<i64, VmError> {
+        let p = self.next_value()?;
+        let mode = mode_digit(code, i);
+        let value = match mode {
+            // Position
+            0 => self.read_data(p)?,
+            // Immediate
+            1 => p,
+            // Relative
+            2 => self.read_data(self.relative(p))?,
+            _ => return Err(self.fault(ErrorKind::InvalidMode { param: i, mode })),
+        };
+        if self.tracer.is_some() {
+            let mode = Mode::from_digit(mode).unwrap();
+            self.trace(|r| r.operand(mode, p, value, false));
+        }
+        Ok(value)
+    }
+
+    // Resolves parameter `i` of the instruction `code` to the address it
+    // writes to
+    fn write_location(&mut self, code: i64, i: usize) -> Result<usize, VmError> {
+        let mode = mode_digit(code, i);
+        let p = self.next_value()?;
+        let addr = match mode {
+            // Position
+            0 => self.write_address(p)?,
+            // Relative
+            2 => self.write_address(self.relative(p))?,
+            _ => return Err(self.fault(ErrorKind::InvalidMode { param: i, mode })),
+        };
+        if self.tracer.is_some() {
+            let mode = Mode::from_digit(mode).unwrap();
+            self.trace(|r| r.operand(mode, p, addr as i64, true));
+        }
+        Ok(addr)
+    }
+
+    fn jump(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 {
+            return Err(self.fault(ErrorKind::NegativeAddress(target)));
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    /// Runs until the program halts, needs input or hits a breakpoint.
+    /// Outputs are buffered and can be collected with `get_output`,
+    /// `take_output` or `output_str`.
+    pub fn run(&mut self) -> Result<StopReason, VmError> {
+        self.run_within(Budget::unlimited())
+    }
+
+    /// Like `run`, but gives up with `StopReason::BudgetExhausted` once
+    /// `budget` runs out. The budget covers the whole call, not each output.
+    pub fn run_within(&mut self, budget: Budget) -> Result<StopReason, VmError> {
+        self.buffering = true;
+        let stop = self.resume_within(budget);
+        self.buffering = false;
+        stop
+    }
+
+    /// Runs as an async task, awaiting `input` whenever the program reads
+    /// with nothing queued and sending every output to `output`. Resolves
+    /// once the program halts or hits a breakpoint, or with
+    /// `StopReason::NeedsInput` if it wants input after every sender of
+    /// `input` has hung up.
+    ///
+    /// Long stretches without input or output are run in slices, yielding to
+    /// other tasks in between. See `exec` for an executor to run it on.
+    pub async fn run_async(
+        &mut self,
+        input: &Receiver<i64>,
+        output: &Sender<i64>,
+    ) -> Result<StopReason, VmError> {
+        loop {
+            match self.resume_within(Budget::steps(ASYNC_SLICE))? {
+                StopReason::NeedsInput => match input.recv().await {
+                    Some(v) => self.push_input(v),
+                    None => return Ok(StopReason::NeedsInput),
+                },
+                StopReason::Output(v) => output.send(v),
+                StopReason::BudgetExhausted => exec::yield_now().await,
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    /// Runs until the program stops for any reason other than running out of
+    /// budget.
+    ///
+    /// If an instruction faults, the VM is left pointing at it.
+    pub fn resume(&mut self) -> Result<StopReason, VmError> {
+        self.resume_within(Budget::unlimited())
+    }
+
+    /// Like `resume`, but executes at most `max_steps` instructions.
+    pub fn resume_for(&mut self, max_steps: u64) -> Result<StopReason, VmError> {
+        self.resume_within(Budget::steps(max_steps))
+    }
+
+    /// Like `resume`, but stops with `StopReason::BudgetExhausted` once
+    /// `budget` runs out. Deadlines are checked every few thousand
+    /// instructions, so they may overrun slightly.
+    pub fn resume_within(&mut self, budget: Budget) -> Result<StopReason, VmError> {
+        let watching = !self.breakpoints.is_empty() || !self.opcode_breakpoints.is_empty();
+        // Without breakpoints or hooks nothing needs looking at between
+        // instructions, so run them in batches and only check the budget in
+        // between
+        let batched = !watching
+            && self.engine == Engine::Cached
+            && self.tracer.is_none()
+            && self.profiler.is_none();
+        let mut steps = 0;
+        loop {
+            if self.halted {
+                return Ok(StopReason::Halted);
+            }
+            if watching && self.at_breakpoint() {
+                self.paused_at = Some(self.pc);
+                return Ok(StopReason::Breakpoint(self.pc));
+            }
+            if budget.max_steps.is_some_and(|b| steps >= b) {
+                return Ok(StopReason::BudgetExhausted);
+            }
+            if steps % DEADLINE_CHECK_INTERVAL == 0
+                && budget.deadline.is_some_and(|d| Instant::now() >= d)
+            {
+                return Ok(StopReason::BudgetExhausted);
+            }
+
+            let batch = if batched {
+                let left = budget.max_steps.map_or(u64::MAX, |b| b - steps);
+                if budget.deadline.is_some() {
+                    left.min(DEADLINE_CHECK_INTERVAL)
+                } else {
+                    left
+                }
+            } else {
+                1
+            };
+            let (executed, stop) = self.execute(batch)?;
+            steps += executed;
+            if let Some(reason) = stop {
+                return Ok(reason);
+            }
+        }
+    }
+
+    // Executes up to `max` instructions. Stops early, with the reason, after
+    // any instruction that does more than continue to the next one.
+    fn execute(&mut self, max: u64) -> Result<(u64, Option<StopReason>), VmError> {
+        let cached = self.engine == Engine::Cached && self.tracer.is_none();
+        self.waiting_for_input = false;
+        let mut executed = 0;
+        if cached && self.profiler.is_none() && self.code_map.is_none() {
+            let (n, stop) = self.run_cached(max)?;
+            executed = n;
+            if stop.is_some() || executed == max {
+                return Ok((executed, stop));
+            }
+            // Stopped before an instruction that doesn't decode, which the
+            // loop below hands to the interpreter
+        }
+        while executed < max {
+            self.instruction_pc = self.pc;
+            self.instruction = 0;
+            let result = if cached {
+                self.step_cached()
+            } else {
+                self.step()
+            };
+            if let Some(tracer) = self.tracer.as_mut() {
+                match &result {
+                    Ok(Step::NeedsInput) => tracer.cancel(),
+                    Ok(_) => tracer.end(None),
+                    Err(e) => tracer.end(Some(e)),
+                }
+            }
+            let stop = match result {
+                Ok(Step::Continue) => None,
+                Ok(Step::Output(v)) => Some(StopReason::Output(v)),
+                Ok(Step::Halted) => Some(StopReason::Halted),
+                Ok(Step::NeedsInput) => {
+                    self.waiting_for_input = true;
+                    return Ok((executed, Some(StopReason::NeedsInput)));
+                }
+                Err(e) => {
+                    self.pc = self.instruction_pc;
+                    return Err(e);
+                }
+            };
+            self.count_executed();
+            self.paused_at = None;
+            executed += 1;
+            if stop.is_some() {
+                return Ok((executed, stop));
+            }
+        }
+        Ok((executed, None))
+    }
+
+    fn count_executed(&mut self) {
+        self.executed += 1;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(self.instruction_pc, self.instruction);
+        }
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        if self.paused_at == Some(self.pc) {
+            return false;
+        }
+        self.breakpoints.contains(&self.pc)
+            || Opcode::from_code(self.state.get(self.pc) % 100)
+                .is_some_and(|op| self.opcode_breakpoints.contains(&op))
+    }
+
+    fn step(&mut self) -> Result<Step, VmError> {
+        let op_code = self.next_value()?;
+        self.instruction = op_code;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.begin(self.instruction_pc, op_code, self.relative_base);
+        }
+        if let Some(map) = self.code_map.as_mut() {
+            if let Some(op) = Opcode::from_code(op_code % 100) {
+                map.executed(self.instruction_pc, op.num_params() + 1);
+            }
+        }
+        match op_code % 100 {
+            // Add
+            1 => {
+                let a = self.param(op_code, 0)?;
+                let b = self.param(op_code, 1)?;
+                let pos = self.write_location(op_code, 2)?;
+                let sum = self.add(a, b)?;
+                self.write(pos, sum);
+            }
+            // Mult
+            2 => {
+                let a = self.param(op_code, 0)?;
+                let b = self.param(op_code, 1)?;
+                let pos = self.write_location(op_code, 2)?;
+                let product = self.mul(a, b)?;
+                self.write(pos, product);
+            }
+            // Input
+            3 => {
+                let idx = self.write_location(op_code, 0)?;
+                let input = match self.next_input() {
+                    Some(v) => v,
+                    None => {
+                        self.pc = self.instruction_pc;
+                        return Ok(Step::NeedsInput);
+                    }
+                };
+                self.trace(|r| r.input(input));
+                self.write(idx, input);
+            }
+            // Output
+            4 => {
+                let value = self.param(op_code, 0)?;
+                self.trace(|r| r.output(value));
+                return Ok(self.output(value));
+            }
+            // Jump-Non-Zero
+            5 => {
+                let value = self.param(op_code, 0)?;
+                let target = self.param(op_code, 1)?;
+                if value != 0 {
+                    self.jump(target)?;
+                }
+            }
+            // Jump-Eq-Zero
+            6 => {
+                let value = self.param(op_code, 0)?;
+                let target = self.param(op_code, 1)?;
+                if value == 0 {
+                    self.jump(target)?;
+                }
+            }
+            // Less Than
+            7 => {
+                let a = self.param(op_code, 0)?;
+                let b = self.param(op_code, 1)?;
+                let idx = self.write_location(op_code, 2)?;
+                self.write(idx, if a < b { 1 } else { 0 });
+            }
+            // Equal
+            8 => {
+                let a = self.param(op_code, 0)?;
+                let b = self.param(op_code, 1)?;
+                let idx = self.write_location(op_code, 2)?;
+                self.write(idx, if a == b { 1 } else { 0 });
+            }
+            // Adjust relative base
+            9 => {
+                let delta = self.param(op_code, 0)?;
+                self.relative_base = self.add(self.relative_base, delta)?;
+            }
+            // Halt
+            99 => {
+                self.halted = true;
+                return Ok(Step::Halted);
+            }
+            _ => return Err(self.fault(ErrorKind::UnknownOpcode)),
+        }
+        Ok(Step::Continue)
+    }
+
+    // Same as `step`, but runs a cached decoding of the instruction
+    fn step_cached(&mut self) -> Result<Step, VmError> {
+        let pc = self.pc;
+        let d = match self.cache.get(pc) {
+            Some(d) => *d,
+            None => match Decoded::decode(&self.state, pc) {
+                Some(d) => {
+                    self.cache.insert(pc, d);
+                    d
+                }
+                // Let the interpreter report the fault
+                None => return self.step(),
+            },
+        };
+        self.instruction = self.state.get(pc);
+        self.pc = pc + d.len as usize;
+        if let Some(map) = self.code_map.as_mut() {
+            map.executed(pc, d.len as usize);
+        }
+        match d.opcode {
+            Opcode::Add => {
+                let a = self.operand(&d, 0)?;
+                let b = self.operand(&d, 1)?;
+                let pos = self.target(&d, 2)?;
+                let sum = self.add(a, b)?;
+                self.write(pos, sum);
+            }
+            Opcode::Mul => {
+                let a = self.operand(&d, 0)?;
+                let b = self.operand(&d, 1)?;
+                let pos = self.target(&d, 2)?;
+                let product = self.mul(a, b)?;
+                self.write(pos, product);
+            }
+            Opcode::Input => {
+                let idx = self.target(&d, 0)?;
+                match self.next_input() {
+                    Some(v) => self.write(idx, v),
+                    None => {
+                        self.pc = pc;
+                        return Ok(Step::NeedsInput);
+                    }
+                }
+            }
+            Opcode::Output => {
+                let value = self.operand(&d, 0)?;
+                return Ok(self.output(value));
+            }
+            Opcode::JumpIfTrue => {
+                let value = self.operand(&d, 0)?;
+                let target = self.operand(&d, 1)?;
+                if value != 0 {
+                    self.jump(target)?;
+                }
+            }
+            Opcode::JumpIfFalse => {
+                let value = self.operand(&d, 0)?;
+                let target = self.operand(&d, 1)?;
+                if value == 0 {
+                    self.jump(target)?;
+                }
+            }
+            Opcode::LessThan => {
+                let a = self.operand(&d, 0)?;
+                let b = self.operand(&d, 1)?;
+                let idx = self.target(&d, 2)?;
+                self.write(idx, if a < b { 1 } else { 0 });
+            }
+            Opcode::Equals => {
+                let a = self.operand(&d, 0)?;
+                let b = self.operand(&d, 1)?;
+                let idx = self.target(&d, 2)?;
+                self.write(idx, if a == b { 1 } else { 0 });
+            }
+            Opcode::AdjustBase => {
+                let delta = self.operand(&d, 0)?;
+                self.relative_base = self.add(self.relative_base, delta)?;
+            }
+            Opcode::Halt => {
+                self.halted = true;
+                return Ok(Step::Halted);
+            }
+        }
+        Ok(Step::Continue)
+    }
+
+    // The cached engine with nothing attached that has to see every
+    // instruction: no tracer, profiler or code map. Runs up to `max`
+    // instructions like `execute`, but stops before one that doesn't decode.
+    fn run_cached(&mut self, max: u64) -> Result<(u64, Option<StopReason>), VmError> {
+        let mut executed = 0;
+        let mut stop = None;
+        while executed < max {
+            let pc = self.pc;
+            let d = match self.cache.get(pc) {
+                Some(d) => *d,
+                None => match Decoded::decode(&self.state, pc) {
+                    Some(d) => {
+                        self.cache.insert(pc, d);
+                        d
+                    }
+                    None => break,
+                },
+            };
+            self.pc = pc + d.len as usize;
+            match self.execute_decoded(&d) {
+                Ok(Step::Continue) => {}
+                Ok(Step::Output(v)) => stop = Some(StopReason::Output(v)),
+                Ok(Step::Halted) => stop = Some(StopReason::Halted),
+                Ok(Step::NeedsInput) => {
+                    self.pc = pc;
+                    self.waiting_for_input = true;
+                    stop = Some(StopReason::NeedsInput);
+                    break;
+                }
+                Err(kind) => {
+                    self.pc = pc;
+                    self.instruction_pc = pc;
+                    self.instruction = self.state.get(pc);
+                    self.executed += executed;
+                    return Err(self.fault(kind));
+                }
+            }
+            executed += 1;
+            if stop.is_some() {
+                break;
+            }
+        }
+        self.executed += executed;
+        if executed > 0 {
+            self.paused_at = None;
+        }
+        Ok((executed, stop))
+    }
+
+    // Runs a decoded instruction, with `pc` already past it
+    #[inline(always)]
+    fn execute_decoded(&mut self, d: &Decoded) -> Result<Step, ErrorKind> {
+        match d.opcode {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => {
+                let a = self.load(d, 0)?;
+                let b = self.load(d, 1)?;
+                let pos = self.store_address(d, 2)?;
+                let value = match d.opcode {
+                    Opcode::Add => self.arithmetic.add(a, b).ok_or(ErrorKind::Overflow)?,
+                    Opcode::Mul => self.arithmetic.mul(a, b).ok_or(ErrorKind::Overflow)?,
+                    Opcode::LessThan => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                self.store(pos, value);
+            }
+            Opcode::Input => {
+                let pos = self.store_address(d, 0)?;
+                match self.next_input() {
+                    Some(v) => self.store(pos, v),
+                    None => return Ok(Step::NeedsInput),
+                }
+            }
+            Opcode::Output => {
+                let value = self.load(d, 0)?;
+                return Ok(self.output(value));
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let value = self.load(d, 0)?;
+                let target = self.load(d, 1)?;
+                if (value != 0) == (d.opcode == Opcode::JumpIfTrue) {
+                    if target < 0 {
+                        return Err(ErrorKind::NegativeAddress(target));
+                    }
+                    self.pc = target as usize;
+                }
+            }
+            Opcode::AdjustBase => {
+                let delta = self.load(d, 0)?;
+                self.relative_base = self
+                    .arithmetic
+                    .add(self.relative_base, delta)
+                    .ok_or(ErrorKind::Overflow)?;
+            }
+            Opcode::Halt => {
+                self.halted = true;
+                return Ok(Step::Halted);
+            }
+        }
+        Ok(Step::Continue)
+    }
+
+    #[inline(always)]
+    fn load(&self, d: &Decoded, i: usize) -> Result<i64, ErrorKind> {
+        let addr = match d.modes[i] {
+            Mode::Position => d.args[i],
+            Mode::Immediate => return Ok(d.args[i]),
+            Mode::Relative => self.relative(d.args[i]),
+        };
+        Ok(self.state.get(self.check_address(addr)?))
+    }
+
+    #[inline(always)]
+    fn store_address(&self, d: &Decoded, i: usize) -> Result<usize, ErrorKind> {
+        match d.modes[i] {
+            Mode::Relative => self.check_address(self.relative(d.args[i])),
+            // Decoding never lets an immediate through for a written operand
+            _ => self.check_address(d.args[i]),
+        }
+    }
+
+    #[inline]
+    fn operand(&mut self, d: &Decoded, i: usize) -> Result<i64, VmError> {
+        match d.modes[i] {
+            Mode::Position => self.read_data(d.args[i]),
+            Mode::Immediate => Ok(d.args[i]),
+            Mode::Relative => self.read_data(self.relative(d.args[i])),
+        }
+    }
+
+    #[inline]
+    fn target(&self, d: &Decoded, i: usize) -> Result<usize, VmError> {
+        match d.modes[i] {
+            Mode::Relative => self.write_address(self.relative(d.args[i])),
+            // Decoding never lets an immediate through for a written operand
+            _ => self.write_address(d.args[i]),
+        }
+    }
+
+    fn next_input(&mut self) -> Option<i64> {
+        match self.input.pop_front() {
+            Some(v) => Some(v),
+            None => self.source.as_mut().and_then(|s| s.next_input()),
+        }
+    }
+
+    fn output(&mut self, value: i64) -> Step {
+        match self.sink.as_mut() {
+            Some(sink) => {
+                sink.send_output(value);
+                Step::Continue
+            }
+            None if self.buffering => {
+                self.output.push(value);
+                Step::Continue
+            }
+            None => Step::Output(value),
+        }
+    }
+
+    /// Takes the oldest output buffered by `run`, if any.
+    pub fn get_output(&mut self) -> Option<i64> {
+        if self.output.is_empty() {
+            None
+        } else {
+            Some(self.output.remove(0))
+        }
+    }
+
+    /// Takes every output buffered by `run`.
+    pub fn take_output(&mut self) -> Vec<i64> {
+        self.output.drain(..).collect()
+    }
+
+    /// Takes every output buffered by `run`, decoded as ASCII.
+    pub fn output_str(&mut self) -> String {
+        self.take_output().iter().map(|x| *x as u8 as char).collect()
+    }
+
+    pub fn get_state(&self, pc: usize) -> i64 {
+        self.state.get(pc)
+    }
+
+    /// Writes to memory from outside the program, e.g. to patch in inputs.
+    /// An attached code map doesn't count this as the program modifying
+    /// itself, and can't trap it.
+    pub fn set_state(&mut self, pc: usize, val: i64) {
+        self.store(pc, val);
+    }
+
+    // Writes memory and keeps the cache in step, without any hooks
+    #[inline(always)]
+    fn store(&mut self, addr: usize, val: i64) {
+        self.state.set(addr, val);
+        self.cache.write(addr, val);
+    }
+
+    pub fn push_input(&mut self, input: i64) {
+        self.input.push_back(input);
+    }
+
+    /// Queues every character of `s` as an ASCII input.
+    pub fn write_string(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push_input(c as u8 as i64);
+        }
+    }
+
+    /// Inputs that have been queued but not yet consumed.
+    pub fn pending_input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    /// Reads input from `source` whenever the input queue runs dry.
+    pub fn attach_input<S>(&mut self, source: S)
+    where
+        S: InputSource + 'static,
+    {
+        self.source = Some(Box::new(source));
+    }
+
+    /// Sends every output to `sink`. While a sink is attached `resume` no
+    /// longer stops with `StopReason::Output`.
+    pub fn attach_output<S>(&mut self, sink: S)
+    where
+        S: OutputSink + 'static,
+    {
+        self.sink = Some(Box::new(sink));
+    }
+
+    pub fn detach_input(&mut self) -> Option<Box<dyn InputSource>> {
+        self.source.take()
+    }
+
+    pub fn detach_output(&mut self) -> Option<Box<dyn OutputSink>> {
+        self.sink.take()
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        if engine != self.engine {
+            self.engine = engine;
+            self.cache.clear();
+        }
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    /// Caps memory at `limit` words. Accesses past the cap fault with
+    /// `ErrorKind::AddressOutOfRange` instead of growing memory.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.state.set_limit(limit);
+        // Cached instructions were only checked against the old limit
+        self.cache.clear();
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.state
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: i64) {
+        self.relative_base = relative_base;
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Number of instructions executed since the VM was created.
+    pub fn instructions_executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Whether the last `resume` stopped with `StopReason::NeedsInput`.
+    pub fn waiting_for_input(&self) -> bool {
+        self.waiting_for_input
+    }
+
+    /// Captures memory, registers and the input and output queues.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.state.clone(),
+            pc: self.pc,
+            relative_base: self.relative_base,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            halted: self.halted,
+            waiting_for_input: self.waiting_for_input,
+        }
+    }
+
+    /// Puts the machine back in the state `snapshot` was taken in. Attached
+    /// input, output, tracer and breakpoints are kept, and so are the engine,
+    /// the arithmetic policy and the count of executed instructions, which
+    /// snapshots don't record.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.state = snapshot.memory.clone();
+        self.cache.clear();
+        self.pc = snapshot.pc;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.clone();
+        self.output = snapshot.output.clone();
+        self.halted = snapshot.halted;
+        self.waiting_for_input = snapshot.waiting_for_input;
+        self.paused_at = None;
+    }
+
+    /// A copy of the machine that continues independently from here.
+    ///
+    /// Memory pages and decoded instructions are shared with this VM until
+    /// either side writes to them, so forking is cheap even for large
+    /// programs. It takes `&mut self` because pages this VM has written to
+    /// are handed over to sharing too. Queued input and output, breakpoints
+    /// and the engine are copied; attached input sources, output sinks,
+    /// tracers, profilers and code maps are not.
+    pub fn fork(&mut self) -> VM {
+        VM {
+            state: self.state.fork(),
+            engine: self.engine,
+            arithmetic: self.arithmetic,
+            cache: self.cache.fork(),
+            pc: self.pc,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            source: None,
+            sink: None,
+            halted: self.halted,
+            waiting_for_input: self.waiting_for_input,
+            buffering: false,
+            relative_base: self.relative_base,
+            breakpoints: self.breakpoints.clone(),
+            opcode_breakpoints: self.opcode_breakpoints.clone(),
+            paused_at: self.paused_at,
+            instruction_pc: self.instruction_pc,
+            instruction: self.instruction,
+            tracer: None,
+            profiler: None,
+            code_map: None,
+            executed: self.executed,
+        }
+    }
+
+    /// Writes a record of every executed instruction to `tracer`.
+    pub fn attach_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Detaches the tracer so it can be finished with `Tracer::finish`.
+    pub fn detach_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Counts every executed instruction in `profiler`.
+    pub fn attach_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn detach_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Tracks which words run as code and which are used as data, and what
+    /// happens when code is overwritten, in `map`.
+    pub fn attach_code_map(&mut self, map: CodeMap) {
+        self.code_map = Some(map);
+    }
+
+    pub fn detach_code_map(&mut self) -> Option<CodeMap> {
+        self.code_map.take()
+    }
+
+    pub fn code_map(&self) -> Option<&CodeMap> {
+        self.code_map.as_ref()
+    }
+
+    /// Stops execution before the instruction at `pc` runs.
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Stops execution before any instruction with this opcode runs.
+    pub fn add_opcode_breakpoint(&mut self, opcode: Opcode) {
+        self.opcode_breakpoints.insert(opcode);
+    }
+
+    pub fn remove_opcode_breakpoint(&mut self, opcode: Opcode) -> bool {
+        self.opcode_breakpoints.remove(&opcode)
+    }
+
+    /// Removes every pc and opcode breakpoint.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.opcode_breakpoints.clear();
+    }
+}
+
+impl fmt::Debug for VM {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VM")
+            .field("engine", &self.engine)
+            .field("arithmetic", &self.arithmetic)
+            .field("pc", &self.pc)
+            .field("relative_base", &self.relative_base)
+            .field("halted", &self.halted)
+            .field("executed", &self.executed)
+            .field("input", &self.input)
+            .field("output", &self.output)
+            .field("has_source", &self.source.is_some())
+            .field("has_sink", &self.sink.is_some())
+            .field("tracing", &self.tracer.is_some())
+            .field("profiling", &self.profiler.is_some())
+            .field("mapping_code", &self.code_map.is_some())
+            .field("breakpoints", &self.breakpoints)
+            .field("opcode_breakpoints", &self.opcode_breakpoints)
+            .field("allocated", &self.state.allocated())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fork_shares_written_pages_until_a_write() {
+        let mut program = vec![
+            1101, 2, 3, 1030, // writes to the second page
+            1101, 7, 0, 9, // patches the next instruction's operand
+            1101, 0, 0, 1031, // writes 7 to the second page
+            99,
+        ];
+        program.resize(1100, 0);
+        let mut vm = VM::new(program);
+        assert_eq!(vm.run().unwrap(), StopReason::Halted);
+        assert_eq!(vm.memory().shared(), 0);
+
+        let mut child = vm.fork();
+        for vm in [&vm, &child] {
+            assert_eq!(vm.memory().shared(), vm.memory().allocated());
+            assert!(vm.cache.is_shared());
+        }
+
+        child.set_state(1031, 1);
+        let copied = child.memory().allocated() - child.memory().shared();
+        assert_eq!(copied, 1024);
+        assert!(child.cache.is_shared());
+        assert_eq!(vm.get_state(1031), 7);
+
+        child.set_state(9, 1);
+        assert!(!child.cache.is_shared());
+        assert_eq!(vm.get_state(9), 7);
+    }
+}
@@ -37,6 +37,73 @@ extensions = {
 TEMPLATE = """# advent_of_code_2019
 🎅 My solutions to the 2019 "Advent of Code"
 
+The Rust Intcode days share the interpreter in `intcode/`. Build it with
+`make intcode`, then compile a day from its folder with
+`rustc -O -L ../target dayN.rs`. `make doctest` checks the examples in the
+library's documentation and `make test` runs its unit tests.
+
+VMs decode the program up front and run instructions from that cache,
+decoding anything else the first time it runs; writes to code patch or drop
+the affected entries, so self-modifying programs behave the same.
+`VM::set_engine(Engine::Interpreter)` switches back to decoding every step,
+which is the reference the cache is compared against, and `make bench`
+times the two on days 13, 19 and 23. `VM::fork` branches a machine mid-run;
+the copy shares memory pages and decoded instructions with the original
+until either side writes to them.
+
+`VM::run_async` runs a program as a future that awaits its input on a channel
+and sends its outputs to another; `intcode::exec` has the channels and a
+small single threaded executor. `intcode::amplifier` builds chains and
+feedback loops of amplifiers on top of it, and its `search` tries every phase
+ordering in parallel to find the strongest signal, which is all day7 does.
+
+Arithmetic wraps on overflow whatever the build profile; `VM::set_arithmetic`
+can make it fault (`Arithmetic::Checked`) or clamp (`Arithmetic::Saturating`)
+instead. `intcode::bigint::BigVm` runs programs with words of any size.
+`VM::attach_code_map` tracks which words run as code and which are used as
+data, and records or traps writes to code that has already run.
+`intcode::symbolic` runs straight-line programs with some words left as
+symbols and solves the resulting formula for a target, falling back to a
+parallel brute force when the program branches on them; that's day2's part
+2. The brute force is an `intcode::search::Search`, which runs a program
+once per candidate input across all cores and returns every match, the
+first or the best, in the order the candidates came in; day19 scans its
+grid with one.
+
+`make tools` builds Intcode tooling into `target/`:
+
+* `intcode-asm <source.asm> [-o <program.txt>]` assembles mnemonics, labels
+  and `DATA`/`STRING` directives into a program.
+* `intcode-disasm [--json] [--run [-i <inputs>]] <program.txt>` prints an
+  annotated listing. With `--run` it runs the program under a code map first,
+  lists what actually executed and reports writes to code that already ran.
+* `intcode-debug <program.txt>` is an interactive debugger with stepping,
+  pc and opcode breakpoints, memory patching, input/output queues, JSON
+  Lines execution traces (`trace <file> [A..B] [OP...]`) and snapshots
+  (`save <file>`, `load <file>`) to checkpoint a run and come back to it.
+* `intcode-profile [-i <inputs>] [-a <text>] [--csv <file>] <program.txt>`
+  runs a program and ranks opcodes, mode combinations and hot addresses.
+* `intcode-circuit <circuit.txt> <program.txt>` runs a graph of amplifiers
+  described in a small text format (fan out, fan in, loops and named taps,
+  see `Day1-9/7-circuit.txt`) and prints every value sent along each edge.
+* `intcode-fuzz [--seed N] [--runs N] [--max-steps N]` runs random programs
+  through a separate reference interpreter, both engines, single stepping,
+  tracing, forks and snapshot round trips, plus checked and saturating
+  arithmetic and `BigVm` when they don't overflow, and prints a minimized
+  reproducer if they ever disagree.
+* `intcode-cfg [--run [-i <inputs>]] [-o <graph.dot>] <program.txt>` splits
+  a program into basic blocks and writes its control flow graph as Graphviz
+  DOT. With `--run`, jumps through memory and code the program writes for
+  itself are filled in from a real run, and blocks that still run on into
+  data are marked unresolved.
+* `intcode-decompile [--run [-i <inputs>]] [-o <out.txt>] <program.txt>`
+  rebuilds the functions of a compiled program as C-like pseudocode with
+  `if`, `while` and named arguments and locals, following a real run like
+  `intcode-cfg` with `--run`.
+* `intcode-bench [--runs N] <13.txt> <19.txt> <23.txt>` times the
+  interpreter against the cached engine on days 13, 19 and 23, reporting the
+  median of N runs each, and checks they give the same answers.
+
 
 ## Solutions
 